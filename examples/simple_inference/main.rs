@@ -1,13 +1,14 @@
 use rusty_whisper::Whisper;
 
-fn main() {
+fn main() -> Result<(), rusty_whisper::Error> {
     let whisper = Whisper::new(
         "weights/encoder.onnx",
         "weights/decoder.onnx",
         "weights/multilingual.tiktoken",
         "weights/positional_embedding.npz",
         "weights/mel_filters.npz",
-    );
-    let result = whisper.recognize_from_audio("data/audio.wav", "en");
+    )?;
+    let result = whisper.recognize_from_audio("data/audio.wav", "en")?;
     println!("{}", result);
+    Ok(())
 }
@@ -1,24 +1,35 @@
-use hound::{Error, WavReader};
+use crate::error::{Error, Result};
+use hound::{SampleFormat, WavReader};
 use ndarray_npy::NpzReader;
 use rayon::prelude::*;
 use rustfft::num_complex::ComplexFloat;
 use rustfft::{num_complex::Complex, FftPlanner};
 use std::f32::consts::PI;
 use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+#[cfg(feature = "symphonia")]
+use std::io::{ErrorKind, SeekFrom};
 use tract_onnx::tract_hir::tract_ndarray::{s, Array, Array2};
 
 const N_FFT: usize = 400;
+const RESAMPLE_ZERO_CROSSINGS: f64 = 16.0;
+const RESAMPLE_KAISER_BETA: f64 = 8.6;
+const RESAMPLE_ROLLOFF: f64 = 0.95;
+const RESAMPLE_TABLE_STEPS: usize = 512;
+pub const SAMPLE_RATE: usize = 16000;
+/// Lower rates are treated as corrupt headers; resampling them would inflate
+/// the audio many times over.
+const MIN_SAMPLE_RATE: u32 = 4000;
+pub const HOP_LENGTH: usize = 160;
 pub const N_FRAMES: usize = 3000;
 
-fn pad_audio(audio: &Vec<f32>) -> Vec<f32> {
+fn pad_audio(audio: &[f32]) -> Vec<f32> {
     let audio_len = audio.len();
     let pad_len = N_FFT / 2;
 
     let mut padded_audio = vec![0.0; audio_len + 2 * pad_len];
 
-    for i in 0..audio_len {
-        padded_audio[pad_len + i] = audio[i];
-    }
+    padded_audio[pad_len..pad_len + audio_len].copy_from_slice(audio);
 
     for i in 0..pad_len {
         padded_audio[i] = audio[pad_len - i];
@@ -28,19 +39,254 @@ fn pad_audio(audio: &Vec<f32>) -> Vec<f32> {
     padded_audio
 }
 
-pub fn read_audio(file_path: &str) -> Result<Vec<f32>, Error> {
-    let reader = WavReader::open(file_path)?;
-    let mut audio_data = Vec::new();
-    for sample in reader.into_samples::<i32>() {
-        let sample_value = sample? as f32 / 32768.0;
-        audio_data.push(sample_value);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    /// Average all channels into one.
+    #[default]
+    Downmix,
+    /// Use only the channel with this zero-based index.
+    Select(usize),
+}
+
+pub fn read_audio(file_path: &str, channel_mode: ChannelMode) -> Result<Vec<f32>> {
+    read_audio_from(BufReader::new(File::open(file_path)?), channel_mode)
+}
+
+pub fn read_audio_from<R: Read + Seek>(reader: R, channel_mode: ChannelMode) -> Result<Vec<f32>> {
+    #[cfg(feature = "symphonia")]
+    let reader = {
+        let mut reader = reader;
+        let mut magic = [0; 4];
+        let is_wav = reader.read_exact(&mut magic).is_ok() && &magic == b"RIFF";
+        reader.seek(SeekFrom::Start(0))?;
+        if !is_wav {
+            let mut bytes = vec![];
+            reader.read_to_end(&mut bytes)?;
+            return decode_audio(bytes, channel_mode);
+        }
+        reader
+    };
+    read_wav(WavReader::new(reader)?, channel_mode)
+}
+
+/// Decodes any container and codec known to symphonia (FLAC, MP3, Ogg/Vorbis,
+/// AAC/M4A, ...) into mono samples at 16 kHz.
+#[cfg(feature = "symphonia")]
+fn decode_audio(bytes: Vec<u8>, channel_mode: ChannelMode) -> Result<Vec<f32>> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+    use symphonia::core::errors::Error as SymphoniaError;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let format_error = |err: SymphoniaError| Error::AudioFormat(err.to_string());
+
+    let stream = MediaSourceStream::new(Box::new(std::io::Cursor::new(bytes)), Default::default());
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &Hint::new(),
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(format_error)?
+        .format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| Error::AudioFormat(String::from("no audio track found")))?;
+    let track_id = track.id;
+    let codec_params = track.codec_params.clone();
+    let sample_rate = codec_params
+        .sample_rate
+        .ok_or_else(|| Error::AudioFormat(String::from("unknown sample rate")))?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&codec_params, &DecoderOptions::default())
+        .map_err(format_error)?;
+
+    let mut samples = vec![];
+    let mut channels = codec_params.channels.map_or(1, |channels| channels.count());
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(format_error(err)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                channels = decoded.spec().channels.count();
+                let mut buffer =
+                    SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                buffer.copy_interleaved_ref(decoded);
+                samples.extend_from_slice(buffer.samples());
+            }
+            // A corrupt packet only loses its own few milliseconds of audio.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(err) => return Err(format_error(err)),
+        }
+    }
+
+    check_finite(&samples)?;
+    check_sample_rate(sample_rate)?;
+    let samples = to_mono(&samples, channels, channel_mode)?;
+    Ok(resample(&samples, sample_rate, SAMPLE_RATE as u32))
+}
+
+/// Float audio can hold NaN or infinity, which would poison the spectrogram.
+pub fn check_finite(samples: &[f32]) -> Result<()> {
+    if samples.iter().all(|sample| sample.is_finite()) {
+        Ok(())
+    } else {
+        Err(Error::AudioFormat(String::from(
+            "audio contains NaN or infinite samples",
+        )))
+    }
+}
+
+fn check_sample_rate(sample_rate: u32) -> Result<()> {
+    if sample_rate < MIN_SAMPLE_RATE {
+        return Err(Error::AudioFormat(format!(
+            "sample rate of {} Hz is below {} Hz",
+            sample_rate, MIN_SAMPLE_RATE
+        )));
     }
+    Ok(())
+}
 
-    Ok(audio_data)
+fn read_wav<R: Read>(reader: WavReader<R>, channel_mode: ChannelMode) -> Result<Vec<f32>> {
+    let spec = reader.spec();
+    check_sample_rate(spec.sample_rate)?;
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => {
+            let samples: Vec<f32> = reader
+                .into_samples::<f32>()
+                .collect::<std::result::Result<_, _>>()?;
+            check_finite(&samples)?;
+            samples
+        }
+        SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<std::result::Result<_, _>>()?
+        }
+    };
+
+    let samples = to_mono(&samples, spec.channels as usize, channel_mode)?;
+    Ok(resample(&samples, spec.sample_rate, SAMPLE_RATE as u32))
+}
+
+pub fn to_mono(samples: &[f32], channels: usize, channel_mode: ChannelMode) -> Result<Vec<f32>> {
+    match channel_mode {
+        _ if channels <= 1 => Ok(samples.to_vec()),
+        ChannelMode::Downmix => Ok(samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+            .collect()),
+        ChannelMode::Select(channel) if channel < channels => Ok(samples
+            .chunks(channels)
+            .filter_map(|frame| frame.get(channel).copied())
+            .collect()),
+        ChannelMode::Select(channel) => Err(Error::AudioFormat(format!(
+            "channel {} requested, audio has {} channels",
+            channel, channels
+        ))),
+    }
+}
+
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Kaiser-windowed sinc filter sampled `RESAMPLE_TABLE_STEPS` times per input
+/// sample from the center out to `half_width`.
+fn resample_kernel(band: f64, half_width: f64) -> Vec<f64> {
+    let window_norm = bessel_i0(RESAMPLE_KAISER_BETA);
+    let len = (half_width * RESAMPLE_TABLE_STEPS as f64).ceil() as usize + 1;
+    (0..len)
+        .map(|i| {
+            let d = i as f64 / RESAMPLE_TABLE_STEPS as f64;
+            let x = d / half_width;
+            if x >= 1.0 {
+                return 0.0;
+            }
+            let sinc = if d == 0.0 {
+                1.0
+            } else {
+                (std::f64::consts::PI * band * d).sin() / (std::f64::consts::PI * band * d)
+            };
+            let window = bessel_i0(RESAMPLE_KAISER_BETA * (1.0 - x * x).sqrt()) / window_norm;
+            band * sinc * window
+        })
+        .collect()
 }
 
-pub fn log_mel_spectrogram(audio_data: Vec<f32>, filters: Array2<f32>) -> Array2<f32> {
-    let stft = par_generate_stft(&pad_audio(&audio_data), 400, 160);
+/// Converts mono samples between sample rates with a Kaiser-windowed sinc filter
+/// that also removes content above the lower of the two Nyquist frequencies.
+///
+/// Panics if either rate is zero.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    assert!(
+        from_rate > 0 && to_rate > 0,
+        "sample rates must be positive"
+    );
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = to_rate as f64 / from_rate as f64;
+    // Filter bandwidth as a fraction of the input Nyquist frequency.
+    let band = ratio.min(1.0) * RESAMPLE_ROLLOFF;
+    let half_width = RESAMPLE_ZERO_CROSSINGS / band;
+    let kernel = resample_kernel(band, half_width);
+    let output_len = (samples.len() as f64 * ratio).ceil() as usize;
+
+    (0..output_len)
+        .into_par_iter()
+        .map(|n| {
+            let t = n as f64 / ratio;
+            let first = (t - half_width).ceil().max(0.0) as usize;
+            let last = ((t + half_width).floor() as usize).min(samples.len() - 1);
+
+            let mut acc = 0.0;
+            for (k, &sample) in samples.iter().enumerate().take(last + 1).skip(first) {
+                // Interpolate linearly between the two nearest table entries.
+                let position = (t - k as f64).abs() * RESAMPLE_TABLE_STEPS as f64;
+                let i = position as usize;
+                if i + 1 >= kernel.len() {
+                    continue;
+                }
+                let frac = position - i as f64;
+                let weight = kernel[i] + (kernel[i + 1] - kernel[i]) * frac;
+                acc += sample as f64 * weight;
+            }
+            acc as f32
+        })
+        .collect()
+}
+
+pub fn log_mel_spectrogram(mut audio_data: Vec<f32>, filters: Array2<f32>) -> Array2<f32> {
+    // Reflect padding and framing need at least one full FFT window.
+    if audio_data.len() <= N_FFT {
+        audio_data.resize(N_FFT + 1, 0.0);
+    }
+    let stft = par_generate_stft(&pad_audio(&audio_data), N_FFT, HOP_LENGTH);
     // let stft = generate_stft(&pad_audio(&audio_data), 400, 160);
     let magnitudes: Array2<f32> = Array2::from_shape_fn((stft[0].len(), stft.len()), |(i, j)| {
         let element = stft[j][i].abs();
@@ -49,10 +295,7 @@ pub fn log_mel_spectrogram(audio_data: Vec<f32>, filters: Array2<f32>) -> Array2
     let mel_spec = filters.dot(&magnitudes);
     let clipped_spec = mel_spec.map(|&x| if x < 1e-10 { 1e-10 } else { x });
     let log_spec = clipped_spec.map(|&x| x.log10());
-    let max_value = *log_spec
-        .iter()
-        .max_by(|&x, &y| x.partial_cmp(y).unwrap())
-        .unwrap();
+    let max_value = *log_spec.iter().max_by(|&x, &y| x.total_cmp(y)).unwrap();
     let log_spec = log_spec.map(|&x| {
         if x > max_value - 8.0 {
             x
@@ -60,8 +303,7 @@ pub fn log_mel_spectrogram(audio_data: Vec<f32>, filters: Array2<f32>) -> Array2
             max_value - 8.0
         }
     });
-    let log_spec = log_spec.map(|&x| (x + 4.0) / 4.0);
-    log_spec
+    log_spec.map(|&x| (x + 4.0) / 4.0)
 }
 
 pub fn pad_or_trim(mel: Array2<f32>, length: usize) -> Array2<f32> {
@@ -82,7 +324,7 @@ fn generate_hann_window(size: usize) -> Vec<f32> {
         .collect()
 }
 
-fn par_generate_stft(audio: &Vec<f32>, n_fft: usize, hop_length: usize) -> Vec<Vec<Complex<f32>>> {
+fn par_generate_stft(audio: &[f32], n_fft: usize, hop_length: usize) -> Vec<Vec<Complex<f32>>> {
     let window = generate_hann_window(n_fft);
     let fft = FftPlanner::new().plan_fft_forward(n_fft);
 
@@ -113,9 +355,121 @@ fn par_generate_stft(audio: &Vec<f32>, n_fft: usize, hop_length: usize) -> Vec<V
     stft
 }
 
-pub fn get_mel_filteres(mel_filteres_path: &str) -> Array2<f32> {
-    let file = File::open(mel_filteres_path).expect("Failed to open file");
-    let mut npz = NpzReader::new(file).expect("Failed to read NPZ file");
-    let mel_filters: Array2<f32> = npz.by_index(0).unwrap();
-    mel_filters
+pub fn get_mel_filteres(mel_filteres_path: &str, n_mels: Option<usize>) -> Result<Array2<f32>> {
+    let file = File::open(mel_filteres_path)?;
+    let mut npz = NpzReader::new(file)?;
+    // The reference filter bank file stores both "mel_80" and "mel_128".
+    let by_name = n_mels.and_then(|n_mels| npz.by_name(&format!("mel_{}.npy", n_mels)).ok());
+    match by_name {
+        Some(mel_filters) => Ok(mel_filters),
+        None => Ok(npz.by_index(0)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(frequency: f32, rate: u32, seconds: f32) -> Vec<f32> {
+        (0..(rate as f32 * seconds) as usize)
+            .map(|i| (2.0 * PI * frequency * i as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    /// RMS of the middle half, away from the filter's edge effects.
+    fn rms(samples: &[f32]) -> f32 {
+        let middle = &samples[samples.len() / 4..samples.len() * 3 / 4];
+        (middle.iter().map(|x| x * x).sum::<f32>() / middle.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn resample_passes_tones_below_nyquist() {
+        let resampled = resample(&tone(1000.0, 44100, 1.0), 44100, SAMPLE_RATE as u32);
+        assert_eq!(resampled.len(), SAMPLE_RATE);
+        assert!((rms(&resampled) - 0.5f32.sqrt()).abs() < 0.01);
+
+        let expected = tone(1000.0, SAMPLE_RATE as u32, 1.0);
+        let max_error = resampled[1000..15000]
+            .iter()
+            .zip(&expected[1000..15000])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(max_error < 0.01, "max error {}", max_error);
+    }
+
+    #[test]
+    fn resample_rejects_tones_above_nyquist() {
+        let resampled = resample(&tone(10000.0, 44100, 1.0), 44100, SAMPLE_RATE as u32);
+        assert!(rms(&resampled) < 0.01);
+    }
+
+    #[test]
+    fn resample_upsamples() {
+        let resampled = resample(&tone(1000.0, 8000, 1.0), 8000, SAMPLE_RATE as u32);
+        assert_eq!(resampled.len(), SAMPLE_RATE);
+        assert!((rms(&resampled) - 0.5f32.sqrt()).abs() < 0.01);
+    }
+
+    fn float_wav(samples: &[f32]) -> std::io::Cursor<Vec<u8>> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE as u32,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut bytes = std::io::Cursor::new(vec![]);
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        bytes.set_position(0);
+        bytes
+    }
+
+    #[test]
+    fn rejects_non_finite_float_samples() {
+        let samples = read_audio_from(float_wav(&[0.5, -0.25]), ChannelMode::Downmix).unwrap();
+        assert_eq!(samples, [0.5, -0.25]);
+
+        for bad in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let result = read_audio_from(float_wav(&[0.5, bad]), ChannelMode::Downmix);
+            assert!(matches!(result, Err(Error::AudioFormat(_))));
+        }
+    }
+
+    /// A 16-bit WAV whose header claims `sample_rate`; hound can't write a
+    /// zero rate itself, so the header fields are patched afterwards.
+    fn int_wav(sample_rate: u32) -> std::io::Cursor<Vec<u8>> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut bytes = std::io::Cursor::new(vec![]);
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for _ in 0..100 {
+            writer.write_sample(1000_i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut bytes = bytes.into_inner();
+        bytes[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+        bytes[28..32].copy_from_slice(&(sample_rate * 2).to_le_bytes());
+        std::io::Cursor::new(bytes)
+    }
+
+    #[test]
+    fn rejects_implausible_sample_rates() {
+        for sample_rate in [0, 1, MIN_SAMPLE_RATE - 1] {
+            let result = read_audio_from(int_wav(sample_rate), ChannelMode::Downmix);
+            assert!(
+                matches!(result, Err(Error::AudioFormat(_))),
+                "{} Hz was accepted",
+                sample_rate
+            );
+        }
+        let samples = read_audio_from(int_wav(8000), ChannelMode::Downmix).unwrap();
+        assert_eq!(samples.len(), 200);
+    }
 }
@@ -0,0 +1,465 @@
+mod multipart;
+mod stream;
+
+use clap::Parser;
+use rusty_whisper::{
+    Error, OutputFormat, Task, TranscribeOptions, Transcription, VadOptions, Whisper, WriterOptions,
+};
+use serde_json::json;
+use std::io::{Cursor, Read};
+use std::net::TcpListener;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const EXIT_USAGE: u8 = 2;
+const EXIT_MODEL: u8 = 3;
+
+/// Uploads above this size are rejected, like the hosted API does.
+const MAX_UPLOAD_BYTES: usize = 25 * 1024 * 1024;
+
+/// Serve Whisper over an OpenAI-compatible HTTP API.
+#[derive(Parser, Debug)]
+#[command(name = "rusty-whisper-server", version)]
+struct Args {
+    /// Directory with the model files, as for the rusty-whisper command.
+    #[arg(long, default_value = "weights")]
+    model_dir: PathBuf,
+
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(long, default_value_t = 8000)]
+    port: u16,
+
+    /// Number of requests handled at the same time.
+    #[arg(long, default_value_t = 1)]
+    workers: usize,
+
+    /// Only decode speech regions found by voice activity detection, with
+    /// silero_vad.onnx from the model directory when it exists.
+    #[arg(long)]
+    vad: bool,
+
+    /// Load alignment.onnx from the model directory so requests can ask for
+    /// word timestamps.
+    #[arg(long)]
+    word_timestamps: bool,
+
+    /// Port of the WebSocket streaming endpoint; defaults to the next port
+    /// after --port.
+    #[arg(long)]
+    stream_port: Option<u16>,
+
+    /// Number of WebSocket streams served at the same time; further
+    /// connections are answered with 503.
+    #[arg(long, default_value_t = 4)]
+    max_streams: usize,
+
+    /// Number of threads used for decoding; defaults to the number of CPUs.
+    #[arg(long)]
+    threads: Option<usize>,
+}
+
+struct State {
+    whisper: Whisper,
+    vad: bool,
+    word_timestamps: bool,
+}
+
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn invalid(message: impl Into<String>) -> ApiError {
+        ApiError::new(400, message)
+    }
+
+    /// The error in the layout of the OpenAI API.
+    fn body(&self) -> String {
+        let error_type = if self.status >= 500 {
+            "server_error"
+        } else {
+            "invalid_request_error"
+        };
+        json!({
+            "error": {
+                "message": self.message,
+                "type": error_type,
+                "param": null,
+                "code": null,
+            }
+        })
+        .to_string()
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> ApiError {
+        match err {
+            Error::UnsupportedLanguage(_)
+            | Error::UnsupportedTask(_)
+            | Error::MissingAlignmentModel
+            | Error::AudioFormat(_)
+            | Error::Audio(_) => ApiError::invalid(err.to_string()),
+            _ => ApiError::new(500, err.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseFormat {
+    Json,
+    Text,
+    Srt,
+    Vtt,
+    VerboseJson,
+}
+
+impl ResponseFormat {
+    fn parse(format: &str) -> Result<ResponseFormat, ApiError> {
+        match format {
+            "json" => Ok(ResponseFormat::Json),
+            "text" => Ok(ResponseFormat::Text),
+            "srt" => Ok(ResponseFormat::Srt),
+            "vtt" => Ok(ResponseFormat::Vtt),
+            "verbose_json" => Ok(ResponseFormat::VerboseJson),
+            _ => Err(ApiError::invalid(format!(
+                "unsupported response_format '{}'; expected json, text, srt, vtt or verbose_json",
+                format
+            ))),
+        }
+    }
+}
+
+/// The fields of a transcription or translation request.
+struct Form {
+    file: Vec<u8>,
+    language: String,
+    prompt: Option<String>,
+    temperature: Option<f32>,
+    response_format: ResponseFormat,
+    word_timestamps: bool,
+}
+
+impl Form {
+    fn parse(parts: Vec<multipart::Part>) -> Result<Form, ApiError> {
+        let mut form = Form {
+            file: vec![],
+            language: "auto".to_string(),
+            prompt: None,
+            temperature: None,
+            response_format: ResponseFormat::Json,
+            word_timestamps: false,
+        };
+        let mut has_file = false;
+        for part in parts {
+            match part.name.as_str() {
+                "file" => {
+                    has_file = true;
+                    form.file = part.data;
+                }
+                "language" => form.language = part.text().map_err(ApiError::invalid)?.to_string(),
+                "prompt" => form.prompt = Some(part.text().map_err(ApiError::invalid)?.to_string()),
+                "temperature" => {
+                    let temperature = part.text().map_err(ApiError::invalid)?;
+                    form.temperature = Some(
+                        temperature
+                            .trim()
+                            .parse()
+                            .ok()
+                            .filter(|temperature| (0.0..=1.0).contains(temperature))
+                            .ok_or_else(|| {
+                                ApiError::invalid("temperature must be a number between 0 and 1")
+                            })?,
+                    );
+                }
+                "response_format" => {
+                    form.response_format =
+                        ResponseFormat::parse(part.text().map_err(ApiError::invalid)?.trim())?
+                }
+                "timestamp_granularities[]" => match part.text().map_err(ApiError::invalid)? {
+                    "word" => form.word_timestamps = true,
+                    "segment" => {}
+                    granularity => {
+                        return Err(ApiError::invalid(format!(
+                            "unsupported timestamp granularity '{}'",
+                            granularity
+                        )))
+                    }
+                },
+                // Fields such as `model` are accepted for compatibility and ignored.
+                _ => {}
+            }
+        }
+        if !has_file {
+            return Err(ApiError::invalid("missing 'file' field"));
+        }
+        Ok(form)
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+fn read_form(request: &mut Request) -> Result<Form, ApiError> {
+    let boundary = header(request, "Content-Type")
+        .and_then(multipart::boundary)
+        .ok_or_else(|| ApiError::invalid("expected a multipart/form-data body"))?;
+    if request
+        .body_length()
+        .is_some_and(|length| length > MAX_UPLOAD_BYTES)
+    {
+        return Err(ApiError::new(413, "request body is too large"));
+    }
+
+    let mut body = vec![];
+    request
+        .as_reader()
+        .take(MAX_UPLOAD_BYTES as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|err| ApiError::invalid(format!("cannot read request body: {}", err)))?;
+    if body.len() > MAX_UPLOAD_BYTES {
+        return Err(ApiError::new(413, "request body is too large"));
+    }
+
+    Form::parse(multipart::parse(&body, &boundary).map_err(ApiError::invalid)?)
+}
+
+fn verbose_json(transcription: &Transcription, task: Task) -> String {
+    let mut result = transcription.to_json();
+    result["task"] = json!(match task {
+        Task::Transcribe => "transcribe",
+        Task::Translate => "translate",
+    });
+    result["text"] = json!(transcription.text.trim());
+    let words: Vec<_> = transcription
+        .segments
+        .iter()
+        .flat_map(|segment| &segment.words)
+        .map(|word| {
+            let mut value = word.to_json();
+            // The API lists words trimmed and without a probability.
+            value["word"] = json!(word.word.trim());
+            value.as_object_mut().unwrap().remove("probability");
+            value
+        })
+        .collect();
+    if !words.is_empty() {
+        result["words"] = json!(words);
+    }
+    result.to_string()
+}
+
+/// Transcribes or translates the uploaded file and returns the content type
+/// and body of the response.
+fn transcribe(
+    state: &State,
+    request: &mut Request,
+    task: Task,
+) -> Result<(&'static str, String), ApiError> {
+    let form = read_form(request)?;
+    if form.word_timestamps && !state.word_timestamps {
+        return Err(ApiError::invalid(
+            "word timestamps need the server to run with --word-timestamps",
+        ));
+    }
+
+    let mut options = TranscribeOptions {
+        task,
+        initial_prompt: form.prompt,
+        word_timestamps: form.word_timestamps,
+        vad: state.vad.then(VadOptions::default),
+        ..Default::default()
+    };
+    // Like the hosted API, a temperature of 0 keeps the fallback schedule.
+    if let Some(temperature) = form.temperature.filter(|&temperature| temperature > 0.0) {
+        options.temperatures = vec![temperature];
+    }
+
+    let transcription =
+        state
+            .whisper
+            .transcribe_reader(Cursor::new(form.file), &form.language, &options)?;
+    let writer_options = WriterOptions::default();
+    Ok(match form.response_format {
+        ResponseFormat::Json => (
+            "application/json",
+            json!({ "text": transcription.text.trim() }).to_string(),
+        ),
+        ResponseFormat::Text => (
+            "text/plain; charset=utf-8",
+            format!("{}\n", transcription.text.trim()),
+        ),
+        ResponseFormat::Srt => (
+            "text/plain; charset=utf-8",
+            transcription.to_format(OutputFormat::Srt, &writer_options),
+        ),
+        ResponseFormat::Vtt => (
+            "text/vtt; charset=utf-8",
+            transcription.to_format(OutputFormat::Vtt, &writer_options),
+        ),
+        ResponseFormat::VerboseJson => ("application/json", verbose_json(&transcription, task)),
+    })
+}
+
+fn route(state: &State, request: &mut Request) -> Result<(&'static str, String), ApiError> {
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let task = match path.as_str() {
+        "/health" => {
+            return match request.method() {
+                Method::Get => Ok(("application/json", json!({ "status": "ok" }).to_string())),
+                _ => Err(ApiError::new(405, "method not allowed")),
+            }
+        }
+        "/v1/audio/transcriptions" => Task::Transcribe,
+        "/v1/audio/translations" => Task::Translate,
+        _ => return Err(ApiError::new(404, format!("no route for {}", path))),
+    };
+    if request.method() != &Method::Post {
+        return Err(ApiError::new(405, "method not allowed"));
+    }
+    transcribe(state, request, task)
+}
+
+fn respond(state: &State, mut request: Request) {
+    let started = Instant::now();
+    // The panic message is printed by the default hook; the client gets a 500.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| route(state, &mut request)))
+        .unwrap_or_else(|_| Err(ApiError::new(500, "internal error")));
+    let (status, content_type, body) = match result {
+        Ok((content_type, body)) => (200, content_type, body),
+        Err(err) => (err.status, "application/json", err.body()),
+    };
+
+    eprintln!(
+        "{} {} -> {} ({:.2}s)",
+        request.method(),
+        request.url(),
+        status,
+        started.elapsed().as_secs_f32()
+    );
+    let content_type = Header::from_bytes("Content-Type", content_type).unwrap();
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type);
+    if let Err(err) = request.respond(response) {
+        eprintln!("error: cannot send response: {}", err);
+    }
+}
+
+/// Reports a worker thread that panicked outside `route`, so the main thread
+/// can start another one.
+struct Worker(Sender<()>);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let _ = self.0.send(());
+        }
+    }
+}
+
+fn spawn_worker(server: &Arc<Server>, state: &Arc<State>, died: &Sender<()>) {
+    let server = Arc::clone(server);
+    let state = Arc::clone(state);
+    let worker = Worker(died.clone());
+    thread::spawn(move || {
+        let _worker = worker;
+        while let Ok(request) = server.recv() {
+            respond(&state, request);
+        }
+    });
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    if let Some(threads) = args.threads {
+        if let Err(err) = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+        {
+            eprintln!("error: cannot set up {} threads: {}", threads, err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    }
+
+    let dir = &args.model_dir;
+    let whisper =
+        match Whisper::from_dir_with(&dir.to_string_lossy(), args.word_timestamps, args.vad) {
+            Ok(whisper) => whisper,
+            Err(err) => {
+                eprintln!("error: cannot load model from {}: {}", dir.display(), err);
+                return ExitCode::from(EXIT_MODEL);
+            }
+        };
+    let state = Arc::new(State {
+        whisper,
+        vad: args.vad,
+        word_timestamps: args.word_timestamps,
+    });
+
+    let address = format!("{}:{}", args.host, args.port);
+    let server = match Server::http(&address) {
+        Ok(server) => Arc::new(server),
+        Err(err) => {
+            eprintln!("error: cannot listen on {}: {}", address, err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let stream_address = format!(
+        "{}:{}",
+        args.host,
+        args.stream_port.unwrap_or(args.port.saturating_add(1))
+    );
+    let stream_listener = match TcpListener::bind(&stream_address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("error: cannot listen on {}: {}", stream_address, err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    eprintln!("Listening on http://{}", address);
+    eprintln!("Streaming on ws://{}{}", stream_address, stream::PATH);
+    {
+        let state = Arc::clone(&state);
+        thread::spawn(move || stream::listen(state, stream_listener, args.max_streams.max(1)));
+    }
+
+    let (died, deaths) = mpsc::channel();
+    for _ in 0..args.workers.max(1) {
+        spawn_worker(&server, &state, &died);
+    }
+    // The server never shuts down, so this only wakes up to replace workers.
+    for () in deaths.iter() {
+        eprintln!("error: a worker died; starting another one");
+        spawn_worker(&server, &state, &died);
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,185 @@
+/// One field of a `multipart/form-data` body.
+#[derive(Debug)]
+pub struct Part {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    pub fn text(&self) -> Result<&str, String> {
+        std::str::from_utf8(&self.data).map_err(|_| format!("'{}' is not valid UTF-8", self.name))
+    }
+}
+
+/// Returns the boundary of a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params.find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        let value = value.trim().trim_matches('"');
+        (key.trim().eq_ignore_ascii_case("boundary") && !value.is_empty())
+            .then(|| value.to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Returns the value of a `name="value"` parameter in a header.
+fn header_param(header: &str, name: &str) -> Option<String> {
+    header.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        (key.trim() == name).then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+pub fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>, String> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let start = find(body, &delimiter).ok_or("multipart boundary not found")?;
+    let mut rest = &body[start + delimiter.len()..];
+
+    let mut parts = vec![];
+    // Every part follows a delimiter line and ends at the next one; the last
+    // delimiter is followed by "--".
+    while !rest.starts_with(b"--") {
+        rest = rest
+            .strip_prefix(b"\r\n")
+            .ok_or("malformed multipart delimiter")?;
+        let headers_end = find(rest, b"\r\n\r\n").ok_or("unterminated multipart headers")?;
+        let headers = std::str::from_utf8(&rest[..headers_end])
+            .map_err(|_| "multipart headers are not valid UTF-8")?;
+        rest = &rest[headers_end + 4..];
+
+        let mut closing = b"\r\n".to_vec();
+        closing.extend_from_slice(&delimiter);
+        let end = find(rest, &closing).ok_or("unterminated multipart body")?;
+        let data = rest[..end].to_vec();
+        rest = &rest[end + closing.len()..];
+
+        let disposition = headers
+            .split("\r\n")
+            .find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.trim()
+                    .eq_ignore_ascii_case("content-disposition")
+                    .then_some(value)
+            })
+            .ok_or("multipart part without Content-Disposition")?;
+        let name =
+            header_param(disposition, "name").ok_or("multipart part without a field name")?;
+        parts.push(Part { name, data });
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(parts: &[&str]) -> Vec<u8> {
+        let mut body = String::new();
+        for part in parts {
+            body.push_str("--XyZ\r\n");
+            body.push_str(part);
+            body.push_str("\r\n");
+        }
+        body.push_str("--XyZ--\r\n");
+        body.into_bytes()
+    }
+
+    #[test]
+    fn reads_the_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=XyZ").as_deref(),
+            Some("XyZ")
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; Boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+        assert_eq!(boundary("application/json; boundary=XyZ"), None);
+    }
+
+    #[test]
+    fn parses_fields_and_files() {
+        let body = body(&[
+            "Content-Disposition: form-data; name=\"language\"\r\n\r\nen",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\n\
+             Content-Type: audio/wav\r\n\r\nRIFF\r\n--Xy\r\n",
+            "content-disposition: form-data; name=empty\r\n\r\n",
+        ]);
+        let parts = parse(&body, "XyZ").unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].name, "language");
+        assert_eq!(parts[0].text(), Ok("en"));
+        // Line breaks and partial delimiters inside the data are kept.
+        assert_eq!(parts[1].name, "file");
+        assert_eq!(parts[1].data, b"RIFF\r\n--Xy\r\n");
+        assert_eq!(parts[2].name, "empty");
+        assert!(parts[2].data.is_empty());
+    }
+
+    #[test]
+    fn skips_the_preamble() {
+        let mut with_preamble = b"ignored\r\n".to_vec();
+        with_preamble.extend(body(&[
+            "Content-Disposition: form-data; name=\"a\"\r\n\r\n1",
+        ]));
+        let parts = parse(&with_preamble, "XyZ").unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].data, b"1");
+
+        assert!(parse(b"--XyZ--\r\n", "XyZ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        let error = |body: &[u8]| parse(body, "XyZ").unwrap_err();
+        assert_eq!(error(b"no delimiter"), "multipart boundary not found");
+        assert_eq!(error(b"--XyZ"), "malformed multipart delimiter");
+        assert_eq!(error(b"--XyZjunk\r\n"), "malformed multipart delimiter");
+        assert_eq!(
+            error(b"--XyZ\r\nContent-Disposition: form-data; name=a\r\n"),
+            "unterminated multipart headers"
+        );
+        assert_eq!(
+            error(b"--XyZ\r\nContent-Disposition: form-data; name=a\r\n\r\ndata"),
+            "unterminated multipart body"
+        );
+        assert_eq!(
+            error(&body(&["Content-Type: text/plain\r\n\r\nx"])),
+            "multipart part without Content-Disposition"
+        );
+        assert_eq!(
+            error(&body(&[
+                "Content-Disposition: form-data; filename=a\r\n\r\nx"
+            ])),
+            "multipart part without a field name"
+        );
+        assert_eq!(
+            error(b"--XyZ\r\n\xff\r\n\r\nx\r\n--XyZ--"),
+            "multipart headers are not valid UTF-8"
+        );
+    }
+
+    #[test]
+    fn rejects_non_utf8_text() {
+        let part = Part {
+            name: "prompt".to_string(),
+            data: vec![0xff],
+        };
+        assert_eq!(part.text(), Err("'prompt' is not valid UTF-8".to_string()));
+    }
+}
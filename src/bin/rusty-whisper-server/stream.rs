@@ -0,0 +1,520 @@
+use crate::{ApiError, State};
+use rusty_whisper::{StreamEvent, StreamingTranscriber, TranscribeOptions, VadOptions};
+use serde_json::json;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use tungstenite::error::ProtocolError;
+use tungstenite::handshake::server::{write_response, ErrorResponse, Request, Response};
+use tungstenite::handshake::HandshakeError;
+use tungstenite::http::{header, HeaderValue, StatusCode};
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+pub const PATH: &str = "/v1/audio/stream";
+
+/// Audio frames queued for the transcriber before the server stops reading
+/// from the socket, which in turn makes the client's writes block.
+const AUDIO_QUEUE: usize = 64;
+
+/// Clients that don't send the upgrade request in time are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Streams that send nothing, not even a ping, for this long are finished,
+/// so idle clients can't hold a slot forever.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connections over capacity waiting for their 503; further ones are closed
+/// without a response.
+const REJECT_QUEUE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleFormat {
+    S16le,
+    F32le,
+}
+
+impl SampleFormat {
+    fn sample_size(&self) -> usize {
+        match self {
+            SampleFormat::S16le => 2,
+            SampleFormat::F32le => 4,
+        }
+    }
+}
+
+/// The `language` and `format` query parameters of an upgrade request.
+struct Session {
+    language: String,
+    format: SampleFormat,
+}
+
+impl Session {
+    fn parse(request: &Request) -> Result<Session, ApiError> {
+        let path = request.uri().path();
+        if path != PATH {
+            return Err(ApiError::new(404, format!("no route for {}", path)));
+        }
+
+        let mut session = Session {
+            language: "auto".to_string(),
+            format: SampleFormat::S16le,
+        };
+        let query = request.uri().query().unwrap_or_default();
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            match key {
+                "language" => session.language = value.to_string(),
+                "format" => {
+                    session.format = match value {
+                        "s16le" => SampleFormat::S16le,
+                        "f32le" => SampleFormat::F32le,
+                        _ => {
+                            return Err(ApiError::invalid(format!(
+                                "unsupported format '{}'; expected s16le or f32le",
+                                value
+                            )))
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(session)
+    }
+}
+
+/// The socket as seen by the WebSocket: reads return the bytes handed over
+/// by `read_audio` and never block, writes go straight to the client.
+struct Connection {
+    stream: TcpStream,
+    received: Vec<u8>,
+    closed: bool,
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.received.is_empty() && !self.closed {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let len = buf.len().min(self.received.len());
+        buf[..len].copy_from_slice(&self.received[..len]);
+        self.received.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+type Socket = Mutex<WebSocket<Connection>>;
+
+fn lock(socket: &Socket) -> MutexGuard<'_, WebSocket<Connection>> {
+    socket
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Counts an open stream until it is dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Serves WebSocket streams, each on its own thread so HTTP workers stay
+/// free, and answers connections beyond `max_streams` with 503.
+pub fn listen(state: Arc<State>, listener: TcpListener, max_streams: usize) {
+    let open = Arc::new(AtomicUsize::new(0));
+    // A rejection can wait up to HANDSHAKE_TIMEOUT for the upgrade request,
+    // so it happens off this loop.
+    let (rejected, rejections) = mpsc::sync_channel::<TcpStream>(REJECT_QUEUE);
+    thread::spawn(move || {
+        for stream in rejections {
+            let _ = accept(&stream, |_| {
+                Err::<(), _>(ApiError::new(
+                    503,
+                    format!("already serving {} streams", max_streams),
+                ))
+            });
+        }
+    });
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("error: {}: {}", PATH, err);
+                continue;
+            }
+        };
+        // Only this loop increments the count, so checking first can't race.
+        if open.load(Ordering::SeqCst) >= max_streams {
+            let _ = rejected.try_send(stream);
+            continue;
+        }
+        open.fetch_add(1, Ordering::SeqCst);
+        let slot = Slot(Arc::clone(&open));
+        let state = Arc::clone(&state);
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Some(session) = accept(&stream, Session::parse) {
+                serve(&state, stream, &session);
+            }
+        });
+    }
+}
+
+fn error_response(err: &ApiError) -> ErrorResponse {
+    let body = err.body();
+    let mut response = ErrorResponse::new(None);
+    *response.status_mut() =
+        StatusCode::from_u16(err.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+    headers.insert(header::CONNECTION, HeaderValue::from_static("close"));
+    *response.body_mut() = Some(body);
+    response
+}
+
+/// Reads the upgrade request and completes the handshake if `check` accepts
+/// it; otherwise the client gets the error as a JSON response.
+fn accept<T>(stream: &TcpStream, check: impl FnOnce(&Request) -> Result<T, ApiError>) -> Option<T> {
+    let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+    let mut checked = None;
+    // The error type is set by tungstenite's `Callback`.
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        let result = check(request);
+        let status = result.as_ref().map_or_else(|err| err.status, |_| 101);
+        eprintln!("GET {} -> {}", request.uri(), status);
+        match result {
+            Ok(value) => {
+                checked = Some(value);
+                Ok(response)
+            }
+            Err(err) => Err(error_response(&err)),
+        }
+    };
+    let result = tungstenite::accept_hdr(stream, callback);
+    let _ = stream.set_read_timeout(Some(IDLE_TIMEOUT));
+
+    match result {
+        Ok(_) => checked,
+        // Requests tungstenite rejects before the callback get no response
+        // from it.
+        Err(HandshakeError::Failure(tungstenite::Error::Protocol(err))) => {
+            let err = match err {
+                ProtocolError::WrongHttpMethod => ApiError::new(405, "method not allowed"),
+                _ => ApiError::new(426, "expected a WebSocket upgrade"),
+            };
+            eprintln!("{} -> {}", PATH, err.status);
+            let mut output = vec![];
+            if write_response(&mut output, &error_response(&err)).is_ok() {
+                output.extend_from_slice(err.body().as_bytes());
+                let mut writer = stream;
+                let _ = writer.write_all(&output);
+            }
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+fn event_message(event: &StreamEvent) -> String {
+    let (kind, segment) = match event {
+        StreamEvent::Provisional(segment) => ("partial", segment),
+        StreamEvent::Final(segment) => ("final", segment),
+    };
+    let segment = segment.to_json();
+    json!({
+        "type": kind,
+        "start": segment["start"],
+        "end": segment["end"],
+        "text": segment["text"],
+    })
+    .to_string()
+}
+
+fn error_message(message: String) -> String {
+    json!({ "type": "error", "message": message }).to_string()
+}
+
+/// Decodes little-endian PCM, keeping a trailing partial sample in `pending`
+/// for the next frame.
+fn decode_samples(pending: &mut Vec<u8>, data: &[u8], format: SampleFormat) -> Vec<f32> {
+    pending.extend_from_slice(data);
+    let complete = pending.len() - pending.len() % format.sample_size();
+    let samples = pending[..complete]
+        .chunks_exact(format.sample_size())
+        .map(|bytes| match format {
+            SampleFormat::S16le => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            SampleFormat::F32le => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        })
+        .collect();
+    pending.drain(..complete);
+    samples
+}
+
+/// Runs the transcriber until the audio channel closes, then flushes it.
+fn transcribe(state: &State, language: &str, audio: Receiver<Vec<f32>>, events: Sender<String>) {
+    let options = TranscribeOptions {
+        vad: state.vad.then(VadOptions::default),
+        ..Default::default()
+    };
+    let mut transcriber = StreamingTranscriber::new(&state.whisper, language, options);
+    while let Ok(mut samples) = audio.recv() {
+        // Catch up on audio that arrived during the last pass in one decode.
+        while let Ok(more) = audio.try_recv() {
+            samples.extend(more);
+        }
+        match transcriber.push(&samples) {
+            Ok(pushed) => {
+                for event in &pushed {
+                    let _ = events.send(event_message(event));
+                }
+            }
+            Err(err) => {
+                let _ = events.send(error_message(err.to_string()));
+                return;
+            }
+        }
+    }
+    match transcriber.finish() {
+        Ok(finished) => {
+            for event in &finished {
+                let _ = events.send(event_message(event));
+            }
+        }
+        Err(err) => {
+            let _ = events.send(error_message(err.to_string()));
+        }
+    }
+}
+
+/// Sends every event as soon as the transcriber produces it, whether or not
+/// the client is sending frames.
+fn write_events(socket: &Socket, events: Receiver<String>) {
+    for event in events {
+        if lock(socket).send(Message::Text(event)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads audio frames until the client sends "EOS", stays idle for
+/// `IDLE_TIMEOUT` or goes away. The socket is only locked while parsing what
+/// arrived, so events are written while this waits for data. Returns whether
+/// the connection is still open.
+fn read_audio(
+    mut stream: &TcpStream,
+    socket: &Socket,
+    format: SampleFormat,
+    audio: SyncSender<Vec<f32>>,
+) -> Result<bool, Box<tungstenite::Error>> {
+    let mut pending = vec![];
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let len = match stream.read(&mut buffer) {
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // Like "EOS", so the audio received so far is still transcribed.
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                lock(socket).send(Message::Text(error_message(format!(
+                    "no data received for {} seconds",
+                    IDLE_TIMEOUT.as_secs()
+                ))))?;
+                return Ok(true);
+            }
+            Err(err) => return Err(Box::new(err.into())),
+        };
+
+        let mut samples = vec![];
+        let mut finished = false;
+        {
+            let mut socket = lock(socket);
+            let connection = socket.get_mut();
+            connection.received.extend_from_slice(&buffer[..len]);
+            connection.closed = len == 0;
+            while !finished {
+                match socket.read() {
+                    Ok(Message::Binary(data)) => {
+                        samples.extend(decode_samples(&mut pending, &data, format));
+                    }
+                    Ok(Message::Text(text)) if text.trim() == "EOS" => finished = true,
+                    Ok(Message::Text(text)) => {
+                        socket.send(Message::Text(error_message(format!(
+                            "unexpected text message '{}'; send audio as binary frames and \"EOS\" to finish",
+                            text
+                        ))))?;
+                    }
+                    Ok(Message::Close(_)) => return Ok(false),
+                    Ok(_) => {}
+                    Err(tungstenite::Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                        break
+                    }
+                    Err(err) => return Err(Box::new(err)),
+                }
+            }
+        }
+
+        // Blocks while the queue is full; fails once the transcriber stopped.
+        if !samples.is_empty() && audio.send(samples).is_err() {
+            return Ok(true);
+        }
+        if finished {
+            return Ok(true);
+        }
+    }
+}
+
+fn serve(state: &State, stream: TcpStream, session: &Session) {
+    let reader = match stream.try_clone() {
+        Ok(reader) => reader,
+        Err(err) => {
+            eprintln!("error: {}: {}", PATH, err);
+            return;
+        }
+    };
+    let connection = Connection {
+        stream,
+        received: vec![],
+        closed: false,
+    };
+    let socket = Mutex::new(WebSocket::from_raw_socket(connection, Role::Server, None));
+
+    let (audio, audio_receiver) = mpsc::sync_channel(AUDIO_QUEUE);
+    let (event_sender, events) = mpsc::channel();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let panicked = event_sender.clone();
+            let language = &session.language;
+            if panic::catch_unwind(AssertUnwindSafe(|| {
+                transcribe(state, language, audio_receiver, event_sender)
+            }))
+            .is_err()
+            {
+                let _ = panicked.send(error_message("internal error".to_string()));
+            }
+        });
+        let writer = scope.spawn(|| write_events(&socket, events));
+
+        // Dropping the audio sender ends the transcriber loop and flushes it,
+        // and the writer returns after sending the last event.
+        match read_audio(&reader, &socket, session.format, audio) {
+            Ok(true) => {
+                let _ = writer.join();
+                let mut socket = lock(&socket);
+                let _ = socket.close(None);
+                let _ = socket.flush();
+            }
+            // The client closed the connection, so the final text can't be sent.
+            Ok(false) => {
+                let _ = lock(&socket).flush();
+            }
+            Err(err) => eprintln!("error: {}: {}", PATH, err),
+        }
+    });
+    let _ = reader.shutdown(Shutdown::Both);
+    eprintln!("{} closed", PATH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusty_whisper::Segment;
+
+    fn parse(uri: &str) -> Result<Session, u16> {
+        let request = Request::builder().uri(uri).body(()).unwrap();
+        Session::parse(&request).map_err(|err| err.status)
+    }
+
+    fn segment(start: f32, end: f32, text: &str) -> Segment {
+        Segment {
+            seek: 0,
+            start,
+            end,
+            text: text.to_string(),
+            tokens: vec![],
+            temperature: 0.0,
+            avg_logprob: -0.2,
+            compression_ratio: 1.0,
+            no_speech_prob: 0.1,
+            words: vec![],
+        }
+    }
+
+    #[test]
+    fn parses_the_query() {
+        let session = parse(PATH).unwrap();
+        assert_eq!(session.language, "auto");
+        assert_eq!(session.format, SampleFormat::S16le);
+
+        let session = parse("/v1/audio/stream?format=f32le&model=x&language=de&").unwrap();
+        assert_eq!(session.language, "de");
+        assert_eq!(session.format, SampleFormat::F32le);
+
+        assert_eq!(parse("/v1/audio/stream?format=wav").err(), Some(400));
+        assert_eq!(parse("/v1/audio/transcriptions").err(), Some(404));
+    }
+
+    #[test]
+    fn carries_partial_samples_over() {
+        let mut pending = vec![];
+        let format = SampleFormat::S16le;
+        assert_eq!(
+            decode_samples(&mut pending, &[0x00, 0x40, 0x00], format),
+            [0.5]
+        );
+        assert_eq!(pending, [0x00]);
+        assert_eq!(decode_samples(&mut pending, &[0xc0], format), [-0.5]);
+        assert!(pending.is_empty());
+
+        let bytes: Vec<u8> = [0.25f32, -1.0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let format = SampleFormat::F32le;
+        assert!(decode_samples(&mut pending, &bytes[..3], format).is_empty());
+        assert_eq!(decode_samples(&mut pending, &bytes[3..6], format), [0.25]);
+        assert_eq!(decode_samples(&mut pending, &bytes[6..], format), [-1.0]);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn formats_events() {
+        let message = event_message(&StreamEvent::Provisional(segment(1.2, 2.8, " Hello")));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&message).unwrap(),
+            json!({ "type": "partial", "start": 1.2, "end": 2.8, "text": " Hello" })
+        );
+
+        let message = event_message(&StreamEvent::Final(segment(0.0, 30.0, " there")));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&message).unwrap(),
+            json!({ "type": "final", "start": 0.0, "end": 30.0, "text": " there" })
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use clap::{ArgAction, Parser};
+use rusty_whisper::{OutputFormat, Task, TranscribeOptions, VadOptions, Whisper, WriterOptions};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const EXIT_FAILED_INPUTS: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_MODEL: u8 = 3;
+
+/// Transcribe or translate audio files with Whisper.
+#[derive(Parser, Debug)]
+#[command(
+    name = "rusty-whisper",
+    version,
+    after_help = "Exit codes: 0 on success, 1 if any input failed, 2 on invalid usage, \
+                  3 if the model could not be loaded."
+)]
+struct Args {
+    /// Audio files or glob patterns such as "recordings/*.wav".
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Directory with encoder.onnx, decoder.onnx, positional_embedding.npz,
+    /// mel_filters.npz and multilingual.tiktoken (or gpt2.tiktoken for
+    /// English-only models); optionally alignment.onnx for word timestamps and
+    /// silero_vad.onnx for VAD.
+    #[arg(long, default_value = "weights")]
+    model_dir: PathBuf,
+
+    /// Spoken language, or "auto" to detect it.
+    #[arg(long, default_value = "auto")]
+    language: String,
+
+    /// Transcribe, or translate to English.
+    #[arg(long, default_value = "transcribe", value_parser = ["transcribe", "translate"])]
+    task: String,
+
+    /// Output formats, separated by commas: txt, vtt, srt, tsv, json or all.
+    #[arg(short = 'f', long, default_value = "all", value_delimiter = ',')]
+    output_format: Vec<String>,
+
+    /// Directory the outputs are written to.
+    #[arg(short = 'o', long, default_value = ".")]
+    output_dir: PathBuf,
+
+    /// Number of beams for beam search; decodes greedily when unset.
+    #[arg(long)]
+    beam_size: Option<usize>,
+
+    /// Number of samples to take at non-zero temperatures.
+    #[arg(long, default_value_t = 5)]
+    best_of: usize,
+
+    /// Temperature to sample with.
+    #[arg(long, default_value_t = 0.0)]
+    temperature: f32,
+
+    /// Temperature increase when decoding fails the thresholds; 0 disables fallback.
+    #[arg(long, default_value_t = 0.2)]
+    temperature_increment_on_fallback: f32,
+
+    /// Only decode speech regions found by voice activity detection.
+    #[arg(long)]
+    vad: bool,
+
+    /// Align every word; needs alignment.onnx in the model directory.
+    #[arg(long)]
+    word_timestamps: bool,
+
+    /// Text to bias the vocabulary of the first window with.
+    #[arg(long)]
+    initial_prompt: Option<String>,
+
+    /// Maximum characters per subtitle line (needs --word-timestamps).
+    #[arg(long)]
+    max_line_width: Option<usize>,
+
+    /// Maximum lines per subtitle (needs --word-timestamps).
+    #[arg(long)]
+    max_line_count: Option<usize>,
+
+    /// Underline words as they are spoken in subtitles (needs --word-timestamps).
+    #[arg(long)]
+    highlight_words: bool,
+
+    /// Number of threads used for decoding; defaults to the number of CPUs.
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Print the segments of every file; repeat for decoding details.
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Only print errors.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+fn output_formats(names: &[String]) -> Result<Vec<OutputFormat>, String> {
+    let all = [
+        OutputFormat::Txt,
+        OutputFormat::Vtt,
+        OutputFormat::Srt,
+        OutputFormat::Tsv,
+        OutputFormat::Json,
+    ];
+    let mut formats = vec![];
+    for name in names {
+        let parsed = if name == "all" {
+            all.to_vec()
+        } else {
+            vec![name.parse()?]
+        };
+        for format in parsed {
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+    }
+    Ok(formats)
+}
+
+fn expand_inputs(inputs: &[String]) -> (Vec<PathBuf>, Vec<String>) {
+    let mut files = vec![];
+    let mut errors = vec![];
+    for input in inputs {
+        if !input.contains(['*', '?', '[']) {
+            if Path::new(input).is_file() {
+                files.push(PathBuf::from(input));
+            } else {
+                errors.push(format!("{}: no such file", input));
+            }
+            continue;
+        }
+
+        match glob::glob(input) {
+            Ok(paths) => {
+                let matched: Vec<PathBuf> = paths
+                    .filter_map(|path| path.ok())
+                    .filter(|path| path.is_file())
+                    .collect();
+                if matched.is_empty() {
+                    errors.push(format!("{}: no files match", input));
+                }
+                files.extend(matched);
+            }
+            Err(err) => errors.push(format!("{}: {}", input, err)),
+        }
+    }
+    (files, errors)
+}
+
+/// Output path of `input` without the format extension. The whole file stem
+/// is kept, so "talk.v2.wav" becomes "talk.v2.srt" rather than "talk.srt".
+fn output_base(output_dir: &Path, input: &Path) -> String {
+    let stem = input.file_stem().unwrap_or(input.as_os_str());
+    output_dir.join(stem).to_string_lossy().into_owned()
+}
+
+/// Drops repeated inputs and reports inputs whose outputs would overwrite
+/// each other, such as "a/x.wav" and "b/x.wav".
+fn check_outputs(files: Vec<PathBuf>, output_dir: &Path) -> Result<Vec<PathBuf>, Vec<String>> {
+    let mut unique: Vec<PathBuf> = vec![];
+    for file in files {
+        if !unique.contains(&file) {
+            unique.push(file);
+        }
+    }
+
+    let mut errors = vec![];
+    for (i, file) in unique.iter().enumerate() {
+        let base = output_base(output_dir, file);
+        if let Some(other) = unique[..i]
+            .iter()
+            .find(|other| output_base(output_dir, other) == base)
+        {
+            errors.push(format!(
+                "{} and {} would both be written to {}.*",
+                other.display(),
+                file.display(),
+                base
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(unique)
+    } else {
+        Err(errors)
+    }
+}
+
+fn transcribe_options(args: &Args) -> TranscribeOptions {
+    let mut temperatures = vec![args.temperature];
+    if args.temperature_increment_on_fallback > 0.0 {
+        let mut temperature = args.temperature + args.temperature_increment_on_fallback;
+        while temperature <= 1.0 + 1e-6 {
+            temperatures.push(temperature);
+            temperature += args.temperature_increment_on_fallback;
+        }
+    }
+
+    TranscribeOptions {
+        task: match args.task.as_str() {
+            "translate" => Task::Translate,
+            _ => Task::Transcribe,
+        },
+        beam_size: args.beam_size,
+        best_of: Some(args.best_of),
+        temperatures,
+        word_timestamps: args.word_timestamps,
+        vad: args.vad.then(VadOptions::default),
+        initial_prompt: args.initial_prompt.clone(),
+        ..Default::default()
+    }
+}
+
+fn transcribe_file(
+    whisper: &Whisper,
+    input: &Path,
+    args: &Args,
+    options: &TranscribeOptions,
+    formats: &[OutputFormat],
+) -> rusty_whisper::Result<()> {
+    let transcription = whisper.transcribe(&input.to_string_lossy(), &args.language, options)?;
+
+    if args.verbose > 0 {
+        if args.language == "auto" {
+            println!("Detected language: {}", transcription.language);
+        }
+        for segment in &transcription.segments {
+            print!(
+                "[{:.2} --> {:.2}] {}",
+                segment.start,
+                segment.end,
+                segment.text.trim()
+            );
+            if args.verbose > 1 {
+                print!(
+                    " (temperature {:.1}, avg logprob {:.2}, compression ratio {:.2}, no speech {:.2})",
+                    segment.temperature,
+                    segment.avg_logprob,
+                    segment.compression_ratio,
+                    segment.no_speech_prob
+                );
+            }
+            println!();
+        }
+    }
+
+    let writer_options = WriterOptions {
+        max_line_width: args.max_line_width,
+        max_line_count: args.max_line_count,
+        highlight_words: args.highlight_words,
+        ..Default::default()
+    };
+    let base = output_base(&args.output_dir, input);
+    for format in formats {
+        let output = PathBuf::from(format!("{}.{}", base, format.extension()));
+        let mut writer = BufWriter::new(File::create(&output)?);
+        transcription.write(&mut writer, *format, &writer_options)?;
+        if !args.quiet {
+            eprintln!("{} -> {}", input.display(), output.display());
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let formats = match output_formats(&args.output_format) {
+        Ok(formats) => formats,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let (files, input_errors) = expand_inputs(&args.inputs);
+    for err in &input_errors {
+        eprintln!("error: {}", err);
+    }
+    if files.is_empty() {
+        return ExitCode::from(EXIT_USAGE);
+    }
+    let files = match check_outputs(files, &args.output_dir) {
+        Ok(files) => files,
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("error: {}", err);
+            }
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    if let Err(err) = std::fs::create_dir_all(&args.output_dir) {
+        eprintln!("error: {}: {}", args.output_dir.display(), err);
+        return ExitCode::from(EXIT_USAGE);
+    }
+
+    if let Some(threads) = args.threads {
+        if let Err(err) = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+        {
+            eprintln!("error: cannot set up {} threads: {}", threads, err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    }
+
+    let dir = &args.model_dir;
+    let whisper =
+        match Whisper::from_dir_with(&dir.to_string_lossy(), args.word_timestamps, args.vad) {
+            Ok(whisper) => whisper,
+            Err(err) => {
+                eprintln!("error: cannot load model from {}: {}", dir.display(), err);
+                return ExitCode::from(EXIT_MODEL);
+            }
+        };
+
+    let options = transcribe_options(&args);
+    let mut failed = input_errors.len();
+    for input in &files {
+        if let Err(err) = transcribe_file(&whisper, input, &args, &options, &formats) {
+            eprintln!("error: {}: {}", input.display(), err);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        if !args.quiet {
+            eprintln!(
+                "{} of {} inputs failed",
+                failed,
+                files.len() + input_errors.len()
+            );
+        }
+        ExitCode::from(EXIT_FAILED_INPUTS)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use tract_onnx::prelude::TractError;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Onnx(TractError),
+    /// Model files that load but don't fit together or aren't Whisper models.
+    InvalidModel(String),
+    Npz(ndarray_npy::ReadNpzError),
+    Tokenizer(String),
+    Audio(hound::Error),
+    AudioFormat(String),
+    UnsupportedLanguage(String),
+    UnsupportedTask(String),
+    /// Word timestamps were requested without loading an alignment model.
+    MissingAlignmentModel,
+    /// The next token could not be sampled, e.g. because every token was suppressed.
+    Sampling(String),
+    /// The decoder was given more positions than its context holds.
+    ContextLength {
+        tokens: usize,
+        n_ctx: usize,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Onnx(err) => write!(f, "ONNX model error: {}", err),
+            Error::InvalidModel(message) => write!(f, "invalid model: {}", message),
+            Error::Npz(err) => write!(f, "NPZ file error: {}", err),
+            Error::Tokenizer(message) => write!(f, "tokenizer error: {}", message),
+            Error::Audio(err) => write!(f, "audio error: {}", err),
+            Error::AudioFormat(message) => write!(f, "unsupported audio: {}", message),
+            Error::UnsupportedLanguage(language) => {
+                write!(f, "unsupported language: {}", language)
+            }
+            Error::UnsupportedTask(message) => write!(f, "unsupported task: {}", message),
+            Error::MissingAlignmentModel => {
+                write!(f, "word timestamps need an alignment model")
+            }
+            Error::Sampling(message) => write!(f, "cannot sample next token: {}", message),
+            Error::ContextLength { tokens, n_ctx } => write!(
+                f,
+                "{} tokens don't fit the decoder context of {}",
+                tokens, n_ctx
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Onnx(err) => Some(err.as_ref()),
+            Error::Npz(err) => Some(err),
+            Error::Audio(err) => Some(err),
+            Error::InvalidModel(_)
+            | Error::Tokenizer(_)
+            | Error::AudioFormat(_)
+            | Error::UnsupportedLanguage(_)
+            | Error::UnsupportedTask(_)
+            | Error::MissingAlignmentModel
+            | Error::Sampling(_)
+            | Error::ContextLength { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<TractError> for Error {
+    fn from(err: TractError) -> Error {
+        Error::Onnx(err)
+    }
+}
+
+impl From<ndarray_npy::ReadNpzError> for Error {
+    fn from(err: ndarray_npy::ReadNpzError) -> Error {
+        Error::Npz(err)
+    }
+}
+
+impl From<hound::Error> for Error {
+    fn from(err: hound::Error) -> Error {
+        Error::Audio(err)
+    }
+}
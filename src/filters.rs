@@ -0,0 +1,134 @@
+use std::fmt::Debug;
+
+/// Adjusts the logits of the next token before it is selected, usually by
+/// setting forbidden tokens to negative infinity. `tokens` holds the tokens
+/// sampled so far in the current window, without the prompt.
+pub trait LogitFilter: Debug + Send + Sync {
+    fn apply(&self, logits: &mut [f32], tokens: &[i32]);
+}
+
+/// Keeps a window from starting with a blank or ending before any text.
+#[derive(Debug, Clone)]
+pub struct SuppressBlank {
+    tokens: Vec<usize>,
+}
+
+impl SuppressBlank {
+    pub fn new(blank_tokens: Vec<usize>, eot_token: usize) -> SuppressBlank {
+        let mut tokens = blank_tokens;
+        tokens.push(eot_token);
+        SuppressBlank { tokens }
+    }
+}
+
+impl LogitFilter for SuppressBlank {
+    fn apply(&self, logits: &mut [f32], tokens: &[i32]) {
+        if tokens.is_empty() {
+            for &token in &self.tokens {
+                logits[token] = f32::NEG_INFINITY;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SuppressTokens {
+    tokens: Vec<usize>,
+}
+
+impl SuppressTokens {
+    pub fn new(tokens: Vec<usize>) -> SuppressTokens {
+        SuppressTokens { tokens }
+    }
+}
+
+impl LogitFilter for SuppressTokens {
+    fn apply(&self, logits: &mut [f32], _tokens: &[i32]) {
+        for &token in &self.tokens {
+            if let Some(logit) = logits.get_mut(token) {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+    }
+}
+
+/// Whisper's timestamp rules: timestamps come in pairs except at the very
+/// start and end, never decrease, and the first token is a timestamp.
+#[derive(Debug, Clone)]
+pub struct TimestampRules {
+    eot_token: usize,
+    no_timestamps_token: usize,
+    timestamp_begin: usize,
+    max_initial_timestamp_index: Option<usize>,
+}
+
+impl TimestampRules {
+    pub fn new(
+        eot_token: usize,
+        no_timestamps_token: usize,
+        timestamp_begin: usize,
+        max_initial_timestamp_index: Option<usize>,
+    ) -> TimestampRules {
+        TimestampRules {
+            eot_token,
+            no_timestamps_token,
+            timestamp_begin,
+            max_initial_timestamp_index,
+        }
+    }
+}
+
+impl LogitFilter for TimestampRules {
+    fn apply(&self, logits: &mut [f32], tokens: &[i32]) {
+        let timestamp_begin = self.timestamp_begin;
+        let n_vocab = logits.len();
+        let is_timestamp = |token: i32| token as usize >= timestamp_begin;
+        logits[self.no_timestamps_token] = f32::NEG_INFINITY;
+
+        let last_was_timestamp = tokens.last().is_some_and(|&token| is_timestamp(token));
+        let penultimate_was_timestamp = tokens.len() < 2 || is_timestamp(tokens[tokens.len() - 2]);
+        if last_was_timestamp {
+            if penultimate_was_timestamp {
+                logits[timestamp_begin..].fill(f32::NEG_INFINITY);
+            } else {
+                logits[..self.eot_token].fill(f32::NEG_INFINITY);
+            }
+        }
+
+        if let Some(&last_timestamp) = tokens.iter().rev().find(|&&token| is_timestamp(token)) {
+            // A closing timestamp may repeat the last one, an opening one may not.
+            let last_timestamp = if last_was_timestamp && !penultimate_was_timestamp {
+                last_timestamp as usize
+            } else {
+                last_timestamp as usize + 1
+            };
+            logits[timestamp_begin..last_timestamp.min(n_vocab)].fill(f32::NEG_INFINITY);
+        }
+
+        if tokens.is_empty() {
+            logits[..timestamp_begin].fill(f32::NEG_INFINITY);
+            if let Some(index) = self.max_initial_timestamp_index {
+                let last_allowed = (timestamp_begin + index + 1).min(n_vocab);
+                logits[last_allowed..].fill(f32::NEG_INFINITY);
+            }
+        }
+
+        // Prefer a timestamp when all timestamps together are more likely than
+        // any single text token.
+        let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        if max == f32::NEG_INFINITY {
+            return;
+        }
+        let timestamp_mass: f32 = logits[timestamp_begin..]
+            .iter()
+            .map(|logit| (logit - max).exp())
+            .sum();
+        let max_text = logits[..timestamp_begin]
+            .iter()
+            .cloned()
+            .fold(f32::NEG_INFINITY, f32::max);
+        if timestamp_mass.ln() + max > max_text {
+            logits[..timestamp_begin].fill(f32::NEG_INFINITY);
+        }
+    }
+}
@@ -1,103 +1,353 @@
 mod audio;
+mod error;
+mod filters;
+mod output;
+mod streaming;
+mod timing;
 mod tokenizers;
 mod utils;
+mod vad;
 
-use audio::{get_mel_filteres, read_audio};
+use audio::{get_mel_filteres, read_audio, read_audio_from};
 use ndarray_npy::NpzReader;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::fs::File;
-use tokenizers::Tokenizer;
-use tract_ndarray::{concatenate, s, Array, Array2, ArrayBase, Axis, Dim, IxDynImpl, OwnedRepr};
+use std::io::{Read, Seek};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use tract_ndarray::{
+    concatenate, s, Array, Array2, Array3, ArrayBase, Axis, Dim, IxDynImpl, OwnedRepr,
+};
 use tract_onnx::prelude::*;
-use utils::{KVCache, Options};
+use tract_onnx::tract_hir::internal::DimLike;
+use utils::{compression_ratio, log_softmax, sequence_score, DecodingResult, KVCache, Options};
+use vad::SileroVad;
+
+pub use audio::{resample, ChannelMode, SAMPLE_RATE};
+pub use error::{Error, Result};
+pub use filters::{LogitFilter, SuppressBlank, SuppressTokens, TimestampRules};
+pub use output::{OutputFormat, WriterOptions};
+pub use streaming::{StreamEvent, StreamingTranscriber};
+pub use tokenizers::Tokenizer;
+pub use utils::{Conditioning, ModelKind, Segment, Task, TranscribeOptions, Transcription, Word};
+pub use vad::{SpeechRegion, VadOptions};
+
+type Model = SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
+type Logits = ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>;
+
+/// Vocabulary size of the English-only `.en` models.
+const ENGLISH_VOCAB_SIZE: usize = 51864;
 
 pub struct Whisper {
-    encoder: SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>,
-    decoder: SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>,
+    encoder: Model,
+    decoder: Model,
     tokenizer: Tokenizer,
     pos_emb: ArrayBase<OwnedRepr<f32>, Dim<[usize; 3]>>,
     mel_filters: Array2<f32>,
+    alignment: Option<Model>,
+    vad: Option<SileroVad>,
     options: Options,
 }
 
 impl Whisper {
+    /// Loads a model, telling English-only models apart from multilingual ones
+    /// by the size of the decoder vocabulary.
     pub fn new(
         encoder_path: &str,
         decoder_path: &str,
         tokenizer_path: &str,
         pos_emb_path: &str,
         mel_filters_path: &str,
-    ) -> Whisper {
+    ) -> Result<Whisper> {
+        Whisper::load(
+            encoder_path,
+            decoder_path,
+            tokenizer_path,
+            pos_emb_path,
+            mel_filters_path,
+            None,
+        )
+    }
+
+    /// Loads a model of the given kind. English-only (`.en`) models expect the
+    /// GPT-2 vocabulary (`gpt2.tiktoken`) as the tokenizer file.
+    pub fn with_model_kind(
+        encoder_path: &str,
+        decoder_path: &str,
+        tokenizer_path: &str,
+        pos_emb_path: &str,
+        mel_filters_path: &str,
+        model_kind: ModelKind,
+    ) -> Result<Whisper> {
+        Whisper::load(
+            encoder_path,
+            decoder_path,
+            tokenizer_path,
+            pos_emb_path,
+            mel_filters_path,
+            Some(model_kind),
+        )
+    }
+
+    /// Loads a model, inferring its kind from the decoder vocabulary when
+    /// `model_kind` is `None`.
+    fn load(
+        encoder_path: &str,
+        decoder_path: &str,
+        tokenizer_path: &str,
+        pos_emb_path: &str,
+        mel_filters_path: &str,
+        model_kind: Option<ModelKind>,
+    ) -> Result<Whisper> {
         let encoder = tract_onnx::onnx()
-            .model_for_path(encoder_path)
-            .unwrap()
-            .into_optimized()
-            .unwrap()
-            .into_runnable()
-            .unwrap();
+            .model_for_path(encoder_path)?
+            .into_optimized()?
+            .into_runnable()?;
         let decoder = tract_onnx::onnx()
-            .model_for_path(decoder_path)
-            .unwrap()
-            .into_optimized()
-            .unwrap()
-            .into_runnable()
-            .unwrap();
-        let tokenizer = Tokenizer::new(tokenizer_path);
+            .model_for_path(decoder_path)?
+            .into_optimized()?
+            .into_runnable()?;
         let pos_emb = {
-            let file = File::open(pos_emb_path).expect("Failed to open file");
-            let mut npz = NpzReader::new(file).expect("Failed to read NPZ file");
-            let pos_emb: Array2<f32> = npz.by_index(0).unwrap();
+            let file = File::open(pos_emb_path)?;
+            let mut npz = NpzReader::new(file)?;
+            let pos_emb: Array2<f32> = npz.by_index(0)?;
             pos_emb.insert_axis(Axis(0))
         };
-        let mel_filters = get_mel_filteres(mel_filters_path);
-        let options = Options::new();
+        let encoder_n_mels = encoder
+            .model()
+            .input_fact(0)
+            .ok()
+            .and_then(|fact| fact.shape.get(1)?.to_usize().ok());
+        let mel_filters = get_mel_filteres(mel_filters_path, encoder_n_mels)?;
+        let n_mels = mel_filters.shape()[0];
+        if let Some(encoder_n_mels) = encoder_n_mels.filter(|&n| n != n_mels) {
+            return Err(Error::InvalidModel(format!(
+                "encoder expects {} mel bins, filter bank has {}",
+                encoder_n_mels, n_mels
+            )));
+        }
+
+        // Multilingual vocabularies hold 51765 regular tokens, the special tokens
+        // and one language token per language; large-v3 added Cantonese.
+        let n_vocab = decoder
+            .model()
+            .output_fact(0)
+            .ok()
+            .and_then(|fact| fact.shape.last()?.to_usize().ok());
+        let model_kind = match (model_kind, n_vocab) {
+            (None, Some(ENGLISH_VOCAB_SIZE)) => ModelKind::English,
+            (None, _) => ModelKind::Multilingual,
+            (Some(ModelKind::English), Some(n_vocab)) if n_vocab != ENGLISH_VOCAB_SIZE => {
+                return Err(Error::InvalidModel(format!(
+                    "English-only models have {} tokens, the decoder has {}",
+                    ENGLISH_VOCAB_SIZE, n_vocab
+                )));
+            }
+            (Some(ModelKind::Multilingual), Some(ENGLISH_VOCAB_SIZE)) => {
+                return Err(Error::InvalidModel(
+                    "the decoder belongs to an English-only model".to_string(),
+                ));
+            }
+            (Some(model_kind), _) => model_kind,
+        };
+        let num_languages = match (model_kind, n_vocab) {
+            (ModelKind::English, _) => 99,
+            (ModelKind::Multilingual, Some(n_vocab)) => n_vocab
+                .checked_sub(51765 + 1)
+                .filter(|&num_languages| num_languages > 0)
+                .ok_or_else(|| {
+                    Error::InvalidModel(format!(
+                        "decoder vocabulary of {} tokens has no language tokens",
+                        n_vocab
+                    ))
+                })?,
+            (ModelKind::Multilingual, None) if n_mels == 128 => 100,
+            (ModelKind::Multilingual, None) => 99,
+        };
+        let tokenizer = Tokenizer::new(tokenizer_path, num_languages)?;
+        // A tokenizer of the other model kind shifts every special token by one.
+        if let Some(n_vocab) = n_vocab.filter(|&n_vocab| n_vocab != tokenizer.vocab_size()) {
+            return Err(Error::InvalidModel(format!(
+                "decoder has {} tokens, {} has {}",
+                n_vocab,
+                tokenizer_path,
+                tokenizer.vocab_size()
+            )));
+        }
+
+        // Decoder inputs are tokens, audio features and positional embedding,
+        // followed by a key and a value cache for every layer.
+        let n_inputs = decoder.model().inputs.len();
+        let n_text_layer = n_inputs
+            .checked_sub(3)
+            .filter(|&n| n > 0 && n % 2 == 0)
+            .map(|n| n / 2)
+            .ok_or_else(|| {
+                Error::InvalidModel(format!(
+                    "decoder has {} inputs, expected tokens, audio features, \
+                     positional embedding and a key and value cache per layer",
+                    n_inputs
+                ))
+            })?;
+        let options = Options::new(
+            &tokenizer,
+            model_kind,
+            pos_emb.shape()[1],
+            pos_emb.shape()[2],
+            n_text_layer,
+        )?;
 
-        Whisper {
+        Ok(Whisper {
             encoder,
             decoder,
             tokenizer,
             pos_emb,
             mel_filters,
+            alignment: None,
+            vad: None,
             options,
+        })
+    }
+
+    /// Loads a model from a directory holding `encoder.onnx`, `decoder.onnx`,
+    /// `positional_embedding.npz`, `mel_filters.npz` and `multilingual.tiktoken`,
+    /// or `gpt2.tiktoken` for English-only models. Optional graphs are added with
+    /// `with_alignment_model` and `with_vad_model`.
+    pub fn from_dir(model_dir: &str) -> Result<Whisper> {
+        let dir = Path::new(model_dir);
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let (tokenizer, model_kind) =
+            if dir.join("gpt2.tiktoken").exists() && !dir.join("multilingual.tiktoken").exists() {
+                ("gpt2.tiktoken", ModelKind::English)
+            } else {
+                ("multilingual.tiktoken", ModelKind::Multilingual)
+            };
+
+        Whisper::with_model_kind(
+            &path("encoder.onnx"),
+            &path("decoder.onnx"),
+            &path(tokenizer),
+            &path("positional_embedding.npz"),
+            &path("mel_filters.npz"),
+            model_kind,
+        )
+    }
+
+    /// Loads a model with `from_dir` plus the optional graphs from the same
+    /// directory: `alignment.onnx`, which must exist when `word_timestamps` is
+    /// set, and `silero_vad.onnx` when `vad` is set and the file exists, so a
+    /// broken VAD graph doesn't stop runs that don't use it.
+    pub fn from_dir_with(model_dir: &str, word_timestamps: bool, vad: bool) -> Result<Whisper> {
+        let dir = Path::new(model_dir);
+        let mut whisper = Whisper::from_dir(model_dir)?;
+        if word_timestamps {
+            let path = dir.join("alignment.onnx");
+            if !path.exists() {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("word timestamps need {}", path.display()),
+                )));
+            }
+            whisper = whisper.with_alignment_model(&path.to_string_lossy())?;
         }
+        let path = dir.join("silero_vad.onnx");
+        if vad && path.exists() {
+            whisper = whisper.with_vad_model(&path.to_string_lossy())?;
+        }
+        Ok(whisper)
+    }
+
+    /// Loads the graph used for word timestamps. It takes the same tokens, audio
+    /// features and positional embedding as the decoder and returns the
+    /// pre-softmax cross-attention weights of the alignment heads, shaped
+    /// `[heads, tokens, audio frames]`.
+    pub fn with_alignment_model(mut self, alignment_path: &str) -> Result<Whisper> {
+        let alignment = tract_onnx::onnx()
+            .model_for_path(alignment_path)?
+            .into_optimized()?
+            .into_runnable()?;
+        self.alignment = Some(alignment);
+        Ok(self)
     }
 
-    fn get_audio_features(&self, mel: Array2<f32>) -> ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>> {
+    /// Loads a Silero VAD graph, used instead of the built-in energy detector
+    /// when `TranscribeOptions::vad` is set.
+    pub fn with_vad_model(mut self, vad_path: &str) -> Result<Whisper> {
+        self.vad = Some(SileroVad::new(vad_path)?);
+        Ok(self)
+    }
+
+    fn get_audio_features(
+        &self,
+        mel: Array2<f32>,
+    ) -> Result<ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>> {
         let mel: Tensor = mel.insert_axis(Axis(0)).into();
         let inputs = tvec!(mel.into());
-        let encoder_out = self.encoder.run(inputs).unwrap()[0]
-            .to_array_view::<f32>()
-            .unwrap()
+        let encoder_out = self.encoder.run(inputs)?[0]
+            .to_array_view::<f32>()?
             .to_owned();
 
-        encoder_out
+        Ok(encoder_out)
     }
 
-    fn get_initial_tokens(&self, prompt: Vec<i32>, language: &str) -> Vec<i32> {
-        let lang_token = self.tokenizer.lang2token.get(language).unwrap();
-        let init_tokens: Vec<i32> = vec![50258, *lang_token as i32, 50359];
-
-        if prompt.len() > 0 {
-            let prev_prompt_len = self.options.n_ctx / 2 - 1;
-            let prompt_tokens: Vec<i32>;
-
-            if prompt.len() > prev_prompt_len {
-                prompt_tokens = prompt[prompt.len() - prev_prompt_len..].to_vec();
-            } else {
-                prompt_tokens = prompt;
+    fn sot_sequence(&self, language: &str, task: Task) -> Result<Vec<i32>> {
+        Ok(match self.options.model_kind {
+            ModelKind::Multilingual => {
+                let lang_token = self
+                    .tokenizer
+                    .lang2token
+                    .get(language)
+                    .ok_or_else(|| Error::UnsupportedLanguage(language.to_string()))?;
+                let task_token = match task {
+                    Task::Transcribe => self.options.transcribe_token,
+                    Task::Translate => self.options.translate_token,
+                };
+                vec![
+                    self.options.sot_token as i32,
+                    *lang_token as i32,
+                    task_token as i32,
+                ]
             }
+            ModelKind::English => match task {
+                Task::Transcribe => vec![self.options.sot_token as i32],
+                Task::Translate => {
+                    return Err(Error::UnsupportedTask(
+                        "English-only models cannot translate".to_string(),
+                    ))
+                }
+            },
+        })
+    }
 
-            let tokens: Vec<i32> = vec![self.options.sot_prev as i32]
-                .into_iter()
-                .chain(prompt_tokens.into_iter())
-                .collect();
-            let tokens: Vec<i32> = tokens.into_iter().chain(init_tokens.into_iter()).collect();
-            tokens
-        } else {
-            let tokens = vec![self.options.sot_prev as i32];
-            let tokens: Vec<i32> = tokens.into_iter().chain(init_tokens.into_iter()).collect();
-            tokens
+    fn get_initial_tokens(
+        &self,
+        prompt: Vec<i32>,
+        language: &str,
+        task: Task,
+        prefix: &[i32],
+    ) -> Result<Vec<i32>> {
+        let mut init_tokens = self.sot_sequence(language, task)?;
+        init_tokens.extend(prefix);
+
+        // Like the reference, <|startofprev|> only appears with prompt text.
+        if prompt.is_empty() {
+            return Ok(init_tokens);
         }
+        let prev_prompt_len = self.options.n_ctx / 2 - 1;
+        let prompt = &prompt[prompt.len().saturating_sub(prev_prompt_len)..];
+        Ok(std::iter::once(self.options.sot_prev as i32)
+            .chain(prompt.iter().copied())
+            .chain(init_tokens)
+            .collect())
+    }
+
+    fn empty_kv_cache(&self) -> KVCache {
+        KVCache::new(self.options.n_text_layer, self.options.n_text_state)
     }
 
     fn inference_logits(
@@ -106,149 +356,829 @@ impl Whisper {
         audio_features: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>,
         kv_cache: KVCache,
         initial_token_length: usize,
-    ) -> (ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>, KVCache) {
-        let offset = kv_cache.k1.shape()[1];
+    ) -> Result<(Logits, KVCache)> {
+        let offset = kv_cache.len();
         let mut tokens = tokens;
 
         if tokens.shape()[1] > initial_token_length {
             tokens = tokens.slice(s![.., -1]).to_owned().insert_axis(Axis(0));
         }
 
-        let pos_emb = self
-            .pos_emb
-            .slice(s![.., offset..offset + tokens.shape()[1], ..])
-            .to_owned();
+        let end = offset + tokens.shape()[1];
+        if end > self.options.n_ctx {
+            return Err(Error::ContextLength {
+                tokens: end,
+                n_ctx: self.options.n_ctx,
+            });
+        }
+        let pos_emb = self.pos_emb.slice(s![.., offset..end, ..]).to_owned();
 
-        let inputs = tvec!(
+        let mut inputs = tvec!(
             tokens.into_tensor().into(),
             audio_features.into_tensor().into(),
             pos_emb.into_tensor().into(),
-            kv_cache.k1.into(),
-            kv_cache.v1.into(),
-            kv_cache.k2.into(),
-            kv_cache.v2.into(),
-            kv_cache.k3.into(),
-            kv_cache.v3.into(),
-            kv_cache.k4.into(),
-            kv_cache.v4.into(),
-            kv_cache.k5.into(),
-            kv_cache.v5.into(),
-            kv_cache.k6.into(),
-            kv_cache.v6.into(),
         );
+        for (k, v) in kv_cache.layers {
+            inputs.push(k.into());
+            inputs.push(v.into());
+        }
 
-        let out = self.decoder.run(inputs).unwrap();
-        let logits = out[0].to_array_view::<f32>().unwrap().to_owned();
-        let k1 = out[1].to_owned().into_tensor();
-        let v1 = out[2].to_owned().into_tensor();
-        let k2 = out[3].to_owned().into_tensor();
-        let v2 = out[4].to_owned().into_tensor();
-        let k3 = out[5].to_owned().into_tensor();
-        let v3 = out[6].to_owned().into_tensor();
-        let k4 = out[7].to_owned().into_tensor();
-        let v4 = out[8].to_owned().into_tensor();
-        let k5 = out[9].to_owned().into_tensor();
-        let v5 = out[10].to_owned().into_tensor();
-        let k6 = out[11].to_owned().into_tensor();
-        let v6 = out[12].to_owned().into_tensor();
-
+        let out = self.decoder.run(inputs)?;
+        let logits = out[0].to_array_view::<f32>()?.to_owned();
         let new_kv_cache = KVCache {
-            k1,
-            k2,
-            k3,
-            k4,
-            k5,
-            k6,
-            v1,
-            v2,
-            v3,
-            v4,
-            v5,
-            v6,
+            layers: out[1..]
+                .chunks(2)
+                .map(|kv| {
+                    (
+                        kv[0].to_owned().into_tensor(),
+                        kv[1].to_owned().into_tensor(),
+                    )
+                })
+                .collect(),
         };
 
-        (logits, new_kv_cache)
+        Ok((logits, new_kv_cache))
     }
 
     fn inference(
+        &self,
+        audio_features: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>,
+        initial_tokens: Vec<i32>,
+        temperature: f32,
+        filters: &[Arc<dyn LogitFilter>],
+        options: &TranscribeOptions,
+        rng: &mut StdRng,
+    ) -> Result<DecodingResult> {
+        let (tokens, sum_logprob, no_speech_prob) = if temperature > 0.0 {
+            let mut samples = vec![];
+            for _ in 0..options.best_of.unwrap_or(1).max(1) {
+                samples.push(self.sampling_search(
+                    audio_features.clone(),
+                    initial_tokens.clone(),
+                    temperature,
+                    filters,
+                    rng,
+                )?);
+            }
+            samples
+                .into_iter()
+                .max_by(|a, b| {
+                    let a = sequence_score(a.0.len(), a.1, options.length_penalty);
+                    let b = sequence_score(b.0.len(), b.1, options.length_penalty);
+                    a.total_cmp(&b)
+                })
+                .unwrap()
+        } else {
+            match options.beam_size {
+                Some(beam_size) if beam_size > 1 => {
+                    self.beam_search(audio_features, initial_tokens, beam_size, filters, options)?
+                }
+                _ => self.sampling_search(audio_features, initial_tokens, 0.0, filters, rng)?,
+            }
+        };
+
+        let text = self.decode_text(&tokens);
+        Ok(DecodingResult {
+            avg_logprob: sum_logprob / (tokens.len() + 1) as f32,
+            compression_ratio: compression_ratio(&text),
+            no_speech_prob,
+            tokens,
+            temperature,
+        })
+    }
+
+    fn decode_with_fallback(
         &self,
         audio_features: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>,
         prompt: Vec<i32>,
+        prefix: &[i32],
         language: &str,
-    ) -> Vec<i32> {
-        let initial_tokens = self.get_initial_tokens(prompt, language);
+        options: &TranscribeOptions,
+        rng: &mut StdRng,
+    ) -> Result<DecodingResult> {
+        let initial_tokens = self.get_initial_tokens(prompt, language, options.task, prefix)?;
+        let filters = self.logit_filters(options);
+        let temperatures = if options.temperatures.is_empty() {
+            vec![0.0]
+        } else {
+            options.temperatures.clone()
+        };
+
+        let mut result = None;
+        for temperature in temperatures {
+            let decoded = self.inference(
+                audio_features.clone(),
+                initial_tokens.clone(),
+                temperature,
+                &filters,
+                options,
+                rng,
+            )?;
+
+            let too_repetitive = options
+                .compression_ratio_threshold
+                .is_some_and(|threshold| decoded.compression_ratio > threshold);
+            let too_unlikely = options
+                .logprob_threshold
+                .is_some_and(|threshold| decoded.avg_logprob < threshold);
+
+            // A silent window will be skipped anyway, so don't retry it.
+            let silent = options
+                .no_speech_threshold
+                .is_some_and(|threshold| decoded.no_speech_prob > threshold)
+                && too_unlikely;
+
+            result = Some(decoded);
+            if silent || (!too_repetitive && !too_unlikely) {
+                break;
+            }
+        }
+
+        // The forced prefix is part of the transcription.
+        let mut result = result.unwrap();
+        result.tokens = prefix.iter().copied().chain(result.tokens).collect();
+        Ok(result)
+    }
+
+    fn logit_filters(&self, options: &TranscribeOptions) -> Vec<Arc<dyn LogitFilter>> {
+        let mut filters: Vec<Arc<dyn LogitFilter>> = vec![];
+        if options.suppress_blank {
+            filters.push(Arc::new(SuppressBlank::new(
+                self.options.blank_tokens.clone(),
+                self.options.eot_token,
+            )));
+        }
+        let suppress_tokens = match &options.suppress_tokens {
+            Some(tokens) => tokens.iter().map(|&token| token as usize).collect(),
+            None => self.options.suppress_tokens.clone(),
+        };
+        filters.push(Arc::new(SuppressTokens::new(suppress_tokens)));
+        // Timestamps are 20 ms apart.
+        let max_initial_timestamp_index = options
+            .max_initial_timestamp
+            .map(|seconds| (seconds / 0.02).round() as usize);
+        filters.push(Arc::new(TimestampRules::new(
+            self.options.eot_token,
+            self.options.no_timestamps_token,
+            self.options.timestamp_begin,
+            max_initial_timestamp_index,
+        )));
+        filters.extend(options.logit_filters.iter().cloned());
+        filters
+    }
+
+    /// Position of the start-of-transcript token, after which the decoder
+    /// predicts the no-speech token.
+    fn sot_index(&self, initial_tokens: &[i32]) -> usize {
+        initial_tokens
+            .iter()
+            .position(|&token| token == self.options.sot_token as i32)
+            .unwrap_or(0)
+    }
+
+    /// Probability of the no-speech token, read from the logits of the first
+    /// decoder step, which cover every initial token.
+    fn no_speech_prob(&self, logits: &Logits, sot_index: usize) -> f32 {
+        let logprobs = log_softmax(&logits.slice(s![0, sot_index, ..]).to_vec());
+        logprobs[self.options.no_speech_token].exp()
+    }
+
+    fn sampling_search(
+        &self,
+        audio_features: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>,
+        initial_tokens: Vec<i32>,
+        temperature: f32,
+        filters: &[Arc<dyn LogitFilter>],
+        rng: &mut StdRng,
+    ) -> Result<(Vec<i32>, f32, f32)> {
         let initial_token_length = initial_tokens.len();
+        let sot_index = self.sot_index(&initial_tokens);
 
         let mut tokens: Array<i32, Dim<[usize; 2]>> =
             Array::from_vec(initial_tokens).insert_axis(Axis(0));
-        let mut kv_cache = KVCache::default();
+        let mut kv_cache = self.empty_kv_cache();
+        let mut sum_logprob = 0.0;
+        let mut no_speech_prob = 0.0;
 
-        for _ in 0..224 {
-            let logits: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>;
+        for step in 0..self.options.sample_len {
+            let logits: Logits;
             (logits, kv_cache) = self.inference_logits(
                 tokens.clone(),
                 audio_features.clone(),
                 kv_cache.clone(),
                 initial_token_length,
-            );
-            let next_word = logits
-                .slice(s![.., -1, ..])
-                .iter()
-                .enumerate()
-                .max_by(|(_, u), (_, v)| u.total_cmp(v))
-                .map(|(i, _)| i as usize)
-                .unwrap();
+            )?;
+            if step == 0 {
+                no_speech_prob = self.no_speech_prob(&logits, sot_index);
+            }
+            let mut logits = logits.slice(s![0, -1, ..]).to_vec();
+            let sampled: Vec<i32> = tokens.iter().skip(initial_token_length).copied().collect();
+            for filter in filters {
+                filter.apply(&mut logits, &sampled);
+            }
+            let logprobs = log_softmax(&logits);
+            let next_word = if temperature > 0.0 {
+                let scaled: Vec<f32> = logits.iter().map(|x| x / temperature).collect();
+                let probs: Vec<f32> = log_softmax(&scaled).iter().map(|x| x.exp()).collect();
+                WeightedIndex::new(&probs)
+                    .map_err(|err| Error::Sampling(err.to_string()))?
+                    .sample(rng)
+            } else {
+                logprobs
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, u), (_, v)| u.total_cmp(v))
+                    .map(|(i, _)| i)
+                    .unwrap()
+            };
 
-            if next_word == self.options.eot_token || tokens.shape()[1] > self.options.n_ctx {
+            // EOT counts towards the score, as in beam search.
+            sum_logprob += logprobs[next_word];
+            if next_word == self.options.eot_token {
                 break;
             }
 
             let next_word_array = Array::from_elem((1, 1), next_word as i32);
             tokens = concatenate!(Axis(1), tokens, next_word_array);
+            if tokens.shape()[1] > self.options.n_ctx {
+                break;
+            }
         }
         tokens = tokens.slice(s![.., initial_token_length..]).to_owned();
-        return tokens.into_raw_vec();
+
+        Ok((tokens.into_raw_vec(), sum_logprob, no_speech_prob))
     }
 
-    fn run(&self, mel: Array2<f32>, language: &str) -> String {
-        let num_frames = mel.shape()[1];
-        let mut seek = 0;
-        let mut segments = vec![];
+    fn beam_search(
+        &self,
+        audio_features: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>,
+        initial_tokens: Vec<i32>,
+        beam_size: usize,
+        filters: &[Arc<dyn LogitFilter>],
+        options: &TranscribeOptions,
+    ) -> Result<(Vec<i32>, f32, f32)> {
+        let initial_token_length = initial_tokens.len();
+        let sot_index = self.sot_index(&initial_tokens);
+        let max_candidates = ((beam_size as f32 * options.patience).round() as usize).max(1);
+
+        let mut beams = vec![(initial_tokens, self.empty_kv_cache(), 0.0)];
+        let mut finished: Vec<(Vec<i32>, f32)> = vec![];
+        let mut no_speech_prob = 0.0;
 
-        while seek < num_frames {
-            let segment: Array2<f32>;
+        for step in 0..self.options.sample_len {
+            let steps: Vec<(Vec<f32>, KVCache, Option<f32>)> = beams
+                .par_iter()
+                .map(|(tokens, kv_cache, _)| {
+                    let (logits, kv_cache) = self.inference_logits(
+                        Array::from_vec(tokens.clone()).insert_axis(Axis(0)),
+                        audio_features.clone(),
+                        kv_cache.clone(),
+                        initial_token_length,
+                    )?;
+                    let no_speech_prob =
+                        (step == 0).then(|| self.no_speech_prob(&logits, sot_index));
+                    let mut logits = logits.slice(s![0, -1, ..]).to_vec();
+                    for filter in filters {
+                        filter.apply(&mut logits, &tokens[initial_token_length..]);
+                    }
+                    Ok((log_softmax(&logits), kv_cache, no_speech_prob))
+                })
+                .collect::<Result<_>>()?;
+            if let Some(prob) = steps.first().and_then(|step| step.2) {
+                no_speech_prob = prob;
+            }
+
+            let mut candidates: Vec<(usize, usize, f32)> = vec![];
+            for (beam_index, ((_, _, sum_logprob), (logprobs, _, _))) in
+                beams.iter().zip(steps.iter()).enumerate()
+            {
+                let mut ranked: Vec<usize> = (0..logprobs.len()).collect();
+                ranked.sort_unstable_by(|&a, &b| logprobs[b].total_cmp(&logprobs[a]));
+                for token in ranked.into_iter().take(beam_size + 1) {
+                    candidates.push((beam_index, token, sum_logprob + logprobs[token]));
+                }
+            }
+            candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
 
-            if seek + audio::N_FRAMES < mel.shape()[1] {
-                segment = mel.slice(s![.., seek..seek + audio::N_FRAMES]).to_owned();
+            let mut next_beams = vec![];
+            for (beam_index, token, sum_logprob) in candidates {
+                let tokens = &beams[beam_index].0;
+                if token == self.options.eot_token {
+                    if finished.len() < max_candidates {
+                        finished.push((tokens[initial_token_length..].to_vec(), sum_logprob));
+                    }
+                } else {
+                    let mut tokens = tokens.clone();
+                    tokens.push(token as i32);
+                    next_beams.push((tokens, steps[beam_index].1.clone(), sum_logprob));
+                    if next_beams.len() == beam_size {
+                        break;
+                    }
+                }
+            }
+            beams = next_beams;
+
+            if finished.len() >= max_candidates
+                || beams.is_empty()
+                || beams[0].0.len() > self.options.n_ctx
+            {
+                break;
+            }
+        }
+
+        if finished.is_empty() {
+            finished = beams
+                .into_iter()
+                .map(|(tokens, _, sum_logprob)| {
+                    (tokens[initial_token_length..].to_vec(), sum_logprob)
+                })
+                .collect();
+        }
+
+        let (tokens, sum_logprob) = finished
+            .into_iter()
+            .max_by(|a, b| {
+                let a = sequence_score(a.0.len(), a.1, options.length_penalty);
+                let b = sequence_score(b.0.len(), b.1, options.length_penalty);
+                a.total_cmp(&b)
+            })
+            .unwrap();
+        Ok((tokens, sum_logprob, no_speech_prob))
+    }
+
+    /// Splits a decoded window at consecutive timestamp tokens and returns the
+    /// segments with the number of frames they cover. An unfinished last
+    /// segment is dropped, to be decoded again from the new seek position.
+    fn split_segments(
+        &self,
+        result: &DecodingResult,
+        seek: usize,
+        segment_size: usize,
+    ) -> (Vec<Segment>, usize) {
+        let tokens = &result.tokens;
+        let timestamp_begin = self.options.timestamp_begin as i32;
+        let is_timestamp = |token: &i32| *token >= timestamp_begin;
+        let timestamp_to_time = |token: i32| {
+            if is_timestamp(&token) {
+                (token - timestamp_begin) as f32 * 0.02
             } else {
-                segment = mel.slice(s![.., seek..]).to_owned();
+                0.0
             }
+        };
+        let frame_duration = audio::HOP_LENGTH as f32 / audio::SAMPLE_RATE as f32;
+        let time_offset = seek as f32 * frame_duration;
+        let new_segment = |start: f32, end: f32, tokens: &[i32]| Segment {
+            seek,
+            start: time_offset + start,
+            end: time_offset + end,
+            text: self.decode_text(tokens),
+            tokens: tokens.to_vec(),
+            temperature: result.temperature,
+            avg_logprob: result.avg_logprob,
+            compression_ratio: result.compression_ratio,
+            no_speech_prob: result.no_speech_prob,
+            words: vec![],
+        };
 
-            segments.push(audio::pad_or_trim(segment, audio::N_FRAMES));
-            seek += audio::N_FRAMES;
+        let single_timestamp_ending = tokens.len() >= 2
+            && !is_timestamp(&tokens[tokens.len() - 2])
+            && is_timestamp(&tokens[tokens.len() - 1]);
+        let mut slices: Vec<usize> = (1..tokens.len())
+            .filter(|&i| is_timestamp(&tokens[i]) && is_timestamp(&tokens[i - 1]))
+            .collect();
+
+        let mut segments = vec![];
+        let consumed = if !slices.is_empty() {
+            if single_timestamp_ending {
+                slices.push(tokens.len());
+            }
+            let mut last_slice = 0;
+            for current_slice in slices {
+                let sliced = &tokens[last_slice..current_slice];
+                segments.push(new_segment(
+                    timestamp_to_time(sliced[0]),
+                    timestamp_to_time(sliced[sliced.len() - 1]),
+                    sliced,
+                ));
+                last_slice = current_slice;
+            }
+
+            if single_timestamp_ending {
+                segment_size
+            } else {
+                // Each timestamp step of 20 ms spans two mel frames.
+                let last_timestamp = tokens[last_slice - 1] - timestamp_begin;
+                match last_timestamp as usize * 2 {
+                    0 => segment_size,
+                    frames => frames,
+                }
+            }
+        } else {
+            let end = match tokens.iter().rev().find(|token| is_timestamp(token)) {
+                Some(&token) if token != timestamp_begin => timestamp_to_time(token),
+                _ => segment_size as f32 * frame_duration,
+            };
+            if tokens.iter().any(|token| !is_timestamp(token)) {
+                segments.push(new_segment(0.0, end, tokens));
+            }
+            segment_size
+        };
+
+        (segments, consumed)
+    }
+
+    fn add_word_timestamps(
+        &self,
+        segments: &mut [Segment],
+        audio_features: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>,
+        language: &str,
+        task: Task,
+        num_frames: usize,
+        time_offset: f32,
+    ) -> Result<()> {
+        let eot_token = self.options.eot_token;
+        let is_text = |token: &i32| (*token as usize) < eot_token;
+        let text_tokens: Vec<i32> = segments
+            .iter()
+            .flat_map(|segment| segment.tokens.iter().copied().filter(is_text))
+            .collect();
+        if text_tokens.is_empty() {
+            return Ok(());
         }
-        let audio_features: Vec<ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>> = segments
-            .par_iter()
-            .map(|segment| self.get_audio_features(segment.clone()))
+        let alignment = self
+            .alignment
+            .as_ref()
+            .ok_or(Error::MissingAlignmentModel)?;
+
+        let mut tokens = self.sot_sequence(language, task)?;
+        let sot_length = tokens.len();
+        tokens.push(self.options.no_timestamps_token as i32);
+        tokens.extend(&text_tokens);
+        tokens.push(eot_token as i32);
+        let n_tokens = tokens.len();
+        let tokens = Array::from_vec(tokens).insert_axis(Axis(0));
+
+        let (logits, _) = self.inference_logits(
+            tokens.clone(),
+            audio_features.clone(),
+            self.empty_kv_cache(),
+            n_tokens,
+        )?;
+        let token_probs: Vec<f32> = text_tokens
+            .iter()
+            .enumerate()
+            .map(|(i, &token)| {
+                let logits = logits.slice(s![0, sot_length + i, ..eot_token]).to_vec();
+                log_softmax(&logits)[token as usize].exp()
+            })
             .collect();
-        let mut result: Vec<i32> = vec![];
-        for audio_feature in audio_features {
-            let tokens = self.inference(audio_feature, result.clone(), language);
-            result.extend(tokens.clone());
+
+        let pos_emb = self.pos_emb.slice(s![.., ..n_tokens, ..]).to_owned();
+        let out = alignment.run(tvec!(
+            tokens.into_tensor().into(),
+            audio_features.into_tensor().into(),
+            pos_emb.into_tensor().into(),
+        ))?;
+        let weights = out[0].to_array_view::<f32>()?;
+        let shape = &weights.shape()[weights.ndim().saturating_sub(3)..];
+        let shape = (shape[0], shape[1], shape[2]);
+        let weights: Array3<f32> = weights
+            .to_owned()
+            .into_shape(shape)
+            .map_err(|err| Error::InvalidModel(format!("unexpected alignment output: {}", err)))?;
+
+        // Each row holds the attention of the step that emitted the next token,
+        // so the rows from <|notimestamps|> up to the last text token are kept.
+        let matrix = timing::alignment_matrix(weights, (num_frames / 2).max(1));
+        let matrix = matrix.slice(s![sot_length..n_tokens - 1, ..]);
+        let (text_indices, time_indices) = timing::dtw(&matrix.mapv(|x| -x));
+        let jump_times = timing::jump_times(&text_indices, &time_indices);
+
+        let (words, word_tokens) = self.split_words(&text_tokens, language);
+
+        let mut boundary = 0;
+        let mut words = words.into_iter().zip(word_tokens).map(|(word, tokens)| {
+            let next = boundary + tokens.len();
+            let probability = token_probs[boundary..next].iter().sum::<f32>() / tokens.len() as f32;
+            let word = Word {
+                word,
+                start: time_offset + jump_times[boundary],
+                end: time_offset + jump_times[next],
+                probability,
+            };
+            boundary = next;
+            (word, tokens.len())
+        });
+
+        for segment in segments.iter_mut() {
+            let n_text_tokens = segment.tokens.iter().filter(|token| is_text(token)).count();
+            let mut consumed = 0;
+            while consumed < n_text_tokens {
+                match words.next() {
+                    Some((word, n_tokens)) => {
+                        consumed += n_tokens;
+                        segment.words.push(word);
+                    }
+                    None => break,
+                }
+            }
         }
 
+        Ok(())
+    }
+
+    fn split_words(&self, tokens: &[i32], language: &str) -> (Vec<String>, Vec<Vec<i32>>) {
+        let split_on_spaces = !["zh", "ja", "th", "lo", "my", "yue"].contains(&language);
+        timing::split_to_word_tokens(
+            tokens,
+            |tokens| {
+                self.tokenizer
+                    .decode(tokens.iter().map(|&token| token as usize).collect())
+            },
+            split_on_spaces,
+        )
+    }
+
+    fn decode_text(&self, tokens: &[i32]) -> String {
         self.tokenizer.decode(
-            result
+            tokens
                 .iter()
                 .map(|v| *v as usize)
-                .filter(|item| item < &50257)
+                .filter(|item| item < &self.options.eot_token)
                 .collect(),
         )
     }
 
-    pub fn recognize_from_audio(&self, audio_path: &str, language: &str) -> String {
-        let audio_data = read_audio(audio_path).unwrap();
+    fn language_probs(
+        &self,
+        audio_features: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>,
+    ) -> Result<HashMap<String, f32>> {
+        let tokens = Array::from_elem((1, 1), self.options.sot_token as i32);
+        let (logits, _) =
+            self.inference_logits(tokens, audio_features, self.empty_kv_cache(), 1)?;
+        let logits = logits.slice(s![0, -1, ..]);
+
+        let languages: Vec<(&String, &usize)> = self.tokenizer.lang2token.iter().collect();
+        let lang_logits: Vec<f32> = languages.iter().map(|(_, token)| logits[**token]).collect();
+        let lang_logprobs = log_softmax(&lang_logits);
+
+        Ok(languages
+            .into_iter()
+            .zip(lang_logprobs)
+            .map(|((language, _), logprob)| (language.clone(), logprob.exp()))
+            .collect())
+    }
+
+    fn speech_ranges(&self, samples: &[f32], options: &VadOptions) -> Result<Vec<Range<usize>>> {
+        let (probs, frame_len) = match &self.vad {
+            Some(vad) => (vad.speech_probs(samples)?, vad::SILERO_FRAME),
+            None => (
+                vad::energy_speech_probs(samples, options),
+                vad::ENERGY_FRAME,
+            ),
+        };
+        Ok(vad::speech_regions(
+            &probs,
+            frame_len,
+            samples.len(),
+            options,
+        ))
+    }
+
+    fn transcribe_audio(
+        &self,
+        samples: &[f32],
+        language: &str,
+        options: &TranscribeOptions,
+        prompt: Vec<i32>,
+    ) -> Result<Transcription> {
+        let prompt = match &options.initial_prompt {
+            Some(initial_prompt) => self
+                .encode(&format!(" {}", initial_prompt.trim()))
+                .into_iter()
+                .chain(prompt)
+                .collect(),
+            None => prompt,
+        };
+        let mel = audio::log_mel_spectrogram(samples.to_vec(), self.mel_filters.clone());
+        let num_frames = mel.shape()[1];
+        let spans = match &options.vad {
+            Some(vad_options) => {
+                let regions = self.speech_ranges(samples, vad_options)?;
+                vad::pack_regions(&regions, audio::N_FRAMES * audio::HOP_LENGTH)
+                    .into_iter()
+                    .map(|range| {
+                        range.start / audio::HOP_LENGTH
+                            ..range.end.div_ceil(audio::HOP_LENGTH).min(num_frames)
+                    })
+                    .collect()
+            }
+            None => std::iter::once(0..num_frames).collect(),
+        };
+        let duration = samples.len() as f32 / audio::SAMPLE_RATE as f32;
+        self.run(mel, spans, duration, language, options, prompt)
+    }
+
+    fn most_likely_language(
+        &self,
+        audio_features: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>,
+    ) -> Result<String> {
+        Ok(self
+            .language_probs(audio_features)?
+            .into_iter()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(language, _)| language)
+            .unwrap())
+    }
+
+    fn run(
+        &self,
+        mel: Array2<f32>,
+        spans: Vec<Range<usize>>,
+        duration: f32,
+        language: &str,
+        options: &TranscribeOptions,
+        prompt: Vec<i32>,
+    ) -> Result<Transcription> {
+        if options.word_timestamps && self.alignment.is_none() {
+            return Err(Error::MissingAlignmentModel);
+        }
+        let mut language = match self.options.model_kind {
+            ModelKind::English => Some(String::from("en")),
+            ModelKind::Multilingual if language == "auto" => None,
+            ModelKind::Multilingual => Some(language.to_string()),
+        };
+
+        let prefix: Vec<i32> = match &options.prefix {
+            Some(prefix) => self.encode(&format!(" {}", prefix.trim())),
+            None => vec![],
+        };
+        let mut rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut result = prompt;
+        let mut prompt_reset_since = 0;
+        let mut segments = vec![];
+        let mut first_window = true;
+        for span in spans {
+            let mut seek = span.start;
+            while seek < span.end {
+                let segment_size = audio::N_FRAMES.min(span.end - seek);
+                let segment = mel.slice(s![.., seek..seek + segment_size]).to_owned();
+                let audio_features =
+                    self.get_audio_features(audio::pad_or_trim(segment, audio::N_FRAMES))?;
+                if language.is_none() {
+                    language = Some(self.most_likely_language(audio_features.clone())?);
+                }
+                let language = language.as_deref().unwrap();
+
+                let prefix = if first_window { prefix.as_slice() } else { &[] };
+                first_window = false;
+                let decoded = self.decode_with_fallback(
+                    audio_features.clone(),
+                    result[prompt_reset_since..].to_vec(),
+                    prefix,
+                    language,
+                    options,
+                    &mut rng,
+                )?;
+                let no_speech = options
+                    .no_speech_threshold
+                    .is_some_and(|threshold| decoded.no_speech_prob > threshold)
+                    && !options
+                        .logprob_threshold
+                        .is_some_and(|threshold| decoded.avg_logprob > threshold);
+                if no_speech {
+                    seek += segment_size;
+                    continue;
+                }
+
+                let (mut window_segments, consumed) =
+                    self.split_segments(&decoded, seek, segment_size);
+                if options.word_timestamps {
+                    let frame_duration = audio::HOP_LENGTH as f32 / audio::SAMPLE_RATE as f32;
+                    self.add_word_timestamps(
+                        &mut window_segments,
+                        audio_features,
+                        language,
+                        options.task,
+                        segment_size,
+                        seek as f32 * frame_duration,
+                    )?;
+                }
+                result.extend(window_segments.iter().flat_map(|segment| &segment.tokens));
+                segments.extend(window_segments);
+                seek += consumed;
+
+                let reset_prompt = match options.conditioning {
+                    Conditioning::Always => false,
+                    Conditioning::Never => true,
+                    Conditioning::ResetOnFallback => {
+                        decoded.temperature > 0.0
+                            || options
+                                .compression_ratio_threshold
+                                .is_some_and(|threshold| decoded.compression_ratio > threshold)
+                    }
+                };
+                if reset_prompt {
+                    prompt_reset_since = result.len();
+                }
+            }
+        }
+
+        let language = match language {
+            Some(language) => language,
+            None => self.most_likely_language(
+                self.get_audio_features(audio::pad_or_trim(mel, audio::N_FRAMES))?,
+            )?,
+        };
+
+        Ok(Transcription {
+            text: segments
+                .iter()
+                .map(|segment| segment.text.as_str())
+                .collect(),
+            segments,
+            language,
+            duration,
+        })
+    }
+
+    pub fn detect_language(&self, audio_path: &str) -> Result<HashMap<String, f32>> {
+        if self.options.model_kind == ModelKind::English {
+            return Ok(HashMap::from([(String::from("en"), 1.0)]));
+        }
+        let audio_data = read_audio(audio_path, ChannelMode::Downmix)?;
         let mel = audio::log_mel_spectrogram(audio_data, self.mel_filters.clone());
-        self.run(mel, language)
+        let mel = audio::pad_or_trim(mel, audio::N_FRAMES);
+        self.language_probs(self.get_audio_features(mel)?)
+    }
+
+    pub fn transcribe(
+        &self,
+        audio_path: &str,
+        language: &str,
+        options: &TranscribeOptions,
+    ) -> Result<Transcription> {
+        let audio_data = read_audio(audio_path, options.channel_mode)?;
+        self.transcribe_samples(&audio_data, language, options)
+    }
+
+    /// Transcribes WAV data from any reader, e.g. a `Cursor` over bytes received
+    /// from the network.
+    pub fn transcribe_reader<R: Read + Seek>(
+        &self,
+        reader: R,
+        language: &str,
+        options: &TranscribeOptions,
+    ) -> Result<Transcription> {
+        let audio_data = read_audio_from(reader, options.channel_mode)?;
+        self.transcribe_samples(&audio_data, language, options)
+    }
+
+    /// Transcribes 16 kHz mono samples in the range [-1.0, 1.0]; use `resample`
+    /// to convert audio recorded at other rates.
+    pub fn transcribe_samples(
+        &self,
+        samples: &[f32],
+        language: &str,
+        options: &TranscribeOptions,
+    ) -> Result<Transcription> {
+        audio::check_finite(samples)?;
+        self.transcribe_audio(samples, language, options, vec![])
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    /// Encodes text into token ids of this model.
+    pub fn encode(&self, text: &str) -> Vec<i32> {
+        self.tokenizer
+            .encode(text)
+            .into_iter()
+            .map(|token| token as i32)
+            .collect()
+    }
+
+    /// Returns the speech regions of 16 kHz mono samples, found with the Silero
+    /// model if one is loaded and with the energy detector otherwise.
+    pub fn detect_speech(
+        &self,
+        samples: &[f32],
+        options: &VadOptions,
+    ) -> Result<Vec<SpeechRegion>> {
+        Ok(self
+            .speech_ranges(samples, options)?
+            .iter()
+            .map(vad::to_speech_region)
+            .collect())
+    }
+
+    pub fn recognize_from_audio(&self, audio_path: &str, language: &str) -> Result<String> {
+        Ok(self
+            .transcribe(audio_path, language, &TranscribeOptions::default())?
+            .text)
     }
 }
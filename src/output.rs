@@ -0,0 +1,471 @@
+use crate::error::Result;
+use crate::utils::{Segment, Transcription, Word};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Txt,
+    Vtt,
+    Srt,
+    Tsv,
+    /// Segments with their words and decoding statistics.
+    Json,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Txt => "txt",
+            OutputFormat::Vtt => "vtt",
+            OutputFormat::Srt => "srt",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> std::result::Result<OutputFormat, String> {
+        match format.to_lowercase().as_str() {
+            "txt" => Ok(OutputFormat::Txt),
+            "vtt" => Ok(OutputFormat::Vtt),
+            "srt" => Ok(OutputFormat::Srt),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format: {}", format)),
+        }
+    }
+}
+
+/// Subtitle layout options; line limits and highlighting need word timestamps.
+#[derive(Debug, Clone, Default)]
+pub struct WriterOptions {
+    /// Maximum number of characters in a line before breaking it.
+    pub max_line_width: Option<usize>,
+    /// Maximum number of lines in a subtitle; segments are ignored when set.
+    pub max_line_count: Option<usize>,
+    /// Maximum number of words in a line.
+    pub max_words_per_line: Option<usize>,
+    /// Underline every word while it is spoken.
+    pub highlight_words: bool,
+}
+
+fn format_timestamp(seconds: f32, always_include_hours: bool, decimal_marker: char) -> String {
+    let milliseconds = (seconds.max(0.0) * 1000.0).round() as u64;
+    let hours = milliseconds / 3_600_000;
+    let minutes = milliseconds / 60_000 % 60;
+    let seconds = milliseconds / 1000 % 60;
+    let milliseconds = milliseconds % 1000;
+    let hours = if always_include_hours || hours > 0 {
+        format!("{:02}:", hours)
+    } else {
+        String::new()
+    };
+    format!(
+        "{}{:02}:{:02}{}{:03}",
+        hours, minutes, seconds, decimal_marker, milliseconds
+    )
+}
+
+/// Groups words into subtitles of at most `max_line_count` lines, following
+/// the reference implementation.
+fn iterate_subtitles(transcription: &Transcription, options: &WriterOptions) -> Vec<Vec<Word>> {
+    let preserve_segments = options.max_line_count.is_none() || options.max_line_width.is_none();
+    let max_line_width = options.max_line_width.unwrap_or(1000);
+    let max_words_per_line = options.max_words_per_line.unwrap_or(1000).max(1);
+
+    let mut subtitles = vec![];
+    let mut subtitle: Vec<Word> = vec![];
+    let mut line_len = 0;
+    let mut line_count = 1;
+    let mut last = transcription
+        .segments
+        .iter()
+        .find_map(|segment| segment.words.first())
+        .map_or(0.0, |word| word.start);
+
+    for segment in &transcription.segments {
+        for chunk in segment.words.chunks(max_words_per_line) {
+            for (i, word) in chunk.iter().enumerate() {
+                let mut word = word.clone();
+                let long_pause = !preserve_segments && word.start - last > 3.0;
+                let has_room = line_len + word.word.chars().count() <= max_line_width;
+                let segment_break = i == 0 && !subtitle.is_empty() && preserve_segments;
+
+                if line_len > 0 && has_room && !long_pause && !segment_break {
+                    line_len += word.word.chars().count();
+                } else {
+                    word.word = word.word.trim().to_string();
+                    let full = options
+                        .max_line_count
+                        .is_some_and(|max_line_count| long_pause || line_count >= max_line_count);
+                    if (!subtitle.is_empty() && full) || segment_break {
+                        subtitles.push(std::mem::take(&mut subtitle));
+                        line_count = 1;
+                    } else if line_len > 0 {
+                        line_count += 1;
+                        word.word = format!("\n{}", word.word);
+                    }
+                    line_len = word.word.trim().chars().count();
+                }
+                last = word.start;
+                subtitle.push(word);
+            }
+        }
+    }
+    if !subtitle.is_empty() {
+        subtitles.push(subtitle);
+    }
+    subtitles
+}
+
+/// Returns the start, end and text of every subtitle cue.
+fn subtitle_cues(
+    transcription: &Transcription,
+    options: &WriterOptions,
+    always_include_hours: bool,
+    decimal_marker: char,
+) -> Vec<(String, String, String)> {
+    let timestamp = |seconds| format_timestamp(seconds, always_include_hours, decimal_marker);
+    let has_words = transcription
+        .segments
+        .iter()
+        .any(|segment| !segment.words.is_empty());
+
+    if !has_words {
+        return transcription
+            .segments
+            .iter()
+            .map(|segment| {
+                (
+                    timestamp(segment.start),
+                    timestamp(segment.end),
+                    segment.text.trim().replace("-->", "->"),
+                )
+            })
+            .collect();
+    }
+
+    let mut cues = vec![];
+    for subtitle in iterate_subtitles(transcription, options) {
+        let start = timestamp(subtitle[0].start);
+        let end = timestamp(subtitle[subtitle.len() - 1].end);
+        let text: String = subtitle.iter().map(|word| word.word.as_str()).collect();
+        if !options.highlight_words {
+            cues.push((start, end, text));
+            continue;
+        }
+
+        let mut last = start;
+        for (i, word) in subtitle.iter().enumerate() {
+            let start = timestamp(word.start);
+            let end = timestamp(word.end);
+            if last != start {
+                cues.push((last, start.clone(), text.clone()));
+            }
+            let highlighted: String = subtitle
+                .iter()
+                .enumerate()
+                .map(|(j, other)| {
+                    if i == j {
+                        let content = other.word.trim_start();
+                        let whitespace = &other.word[..other.word.len() - content.len()];
+                        format!("{}<u>{}</u>", whitespace, content)
+                    } else {
+                        other.word.clone()
+                    }
+                })
+                .collect();
+            cues.push((start, end.clone(), highlighted));
+            last = end;
+        }
+    }
+    cues
+}
+
+/// Widens an f32 without exposing binary noise, so 0.2 stays 0.2 in JSON.
+fn json_float(value: f32) -> f64 {
+    value.to_string().parse().unwrap_or(f64::NAN)
+}
+
+impl Word {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "word": self.word,
+            "start": json_float(self.start),
+            "end": json_float(self.end),
+            "probability": json_float(self.probability),
+        })
+    }
+}
+
+impl Segment {
+    /// The segment in the layout of the reference implementation, without
+    /// its `id`.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "seek": self.seek,
+            "start": json_float(self.start),
+            "end": json_float(self.end),
+            "text": self.text,
+            "tokens": self.tokens,
+            "temperature": json_float(self.temperature),
+            "avg_logprob": json_float(self.avg_logprob),
+            "compression_ratio": json_float(self.compression_ratio),
+            "no_speech_prob": json_float(self.no_speech_prob),
+            "words": self.words.iter().map(Word::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl Transcription {
+    /// The JSON written by `OutputFormat::Json`.
+    pub fn to_json(&self) -> Value {
+        let segments: Vec<Value> = self
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(id, segment)| {
+                let mut value = segment.to_json();
+                value["id"] = json!(id);
+                value
+            })
+            .collect();
+        json!({
+            "text": self.text,
+            "segments": segments,
+            "language": self.language,
+            "duration": json_float(self.duration),
+        })
+    }
+
+    pub fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        format: OutputFormat,
+        options: &WriterOptions,
+    ) -> Result<()> {
+        match format {
+            OutputFormat::Txt => {
+                for segment in &self.segments {
+                    writeln!(writer, "{}", segment.text.trim())?;
+                }
+            }
+            OutputFormat::Vtt => {
+                write!(writer, "WEBVTT\n\n")?;
+                for (start, end, text) in subtitle_cues(self, options, false, '.') {
+                    write!(writer, "{} --> {}\n{}\n\n", start, end, text)?;
+                }
+            }
+            OutputFormat::Srt => {
+                for (i, (start, end, text)) in subtitle_cues(self, options, true, ',')
+                    .into_iter()
+                    .enumerate()
+                {
+                    write!(writer, "{}\n{} --> {}\n{}\n\n", i + 1, start, end, text)?;
+                }
+            }
+            OutputFormat::Tsv => {
+                writeln!(writer, "start\tend\ttext")?;
+                for segment in &self.segments {
+                    writeln!(
+                        writer,
+                        "{}\t{}\t{}",
+                        (segment.start * 1000.0).round() as i64,
+                        (segment.end * 1000.0).round() as i64,
+                        segment.text.trim().replace('\t', " ")
+                    )?;
+                }
+            }
+            OutputFormat::Json => {
+                serde_json::to_writer(&mut *writer, &self.to_json())
+                    .map_err(std::io::Error::from)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_format(&self, format: OutputFormat, options: &WriterOptions) -> String {
+        let mut output = vec![];
+        // Writing to memory only fails on I/O errors, which can't happen here.
+        self.write(&mut output, format, options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(word: &str, start: f32, end: f32) -> Word {
+        Word {
+            word: word.to_string(),
+            start,
+            end,
+            probability: 1.0,
+        }
+    }
+
+    fn segment(start: f32, end: f32, text: &str, words: Vec<Word>) -> Segment {
+        Segment {
+            seek: (start * 100.0) as usize,
+            start,
+            end,
+            text: text.to_string(),
+            tokens: vec![],
+            temperature: 0.0,
+            avg_logprob: -0.25,
+            compression_ratio: 1.5,
+            no_speech_prob: 0.1,
+            words,
+        }
+    }
+
+    fn transcription(segments: Vec<Segment>) -> Transcription {
+        Transcription {
+            text: segments
+                .iter()
+                .map(|segment| segment.text.as_str())
+                .collect(),
+            segments,
+            language: "en".to_string(),
+            duration: 12.0,
+        }
+    }
+
+    #[test]
+    fn timestamps_roll_over_into_hours() {
+        assert_eq!(format_timestamp(65.25, false, '.'), "01:05.250");
+        assert_eq!(format_timestamp(65.25, true, ','), "00:01:05,250");
+        assert_eq!(format_timestamp(3599.9996, false, '.'), "01:00:00.000");
+        assert_eq!(format_timestamp(3725.5, true, ','), "01:02:05,500");
+    }
+
+    #[test]
+    fn writes_segments_without_words() {
+        let transcription = transcription(vec![
+            segment(0.0, 2.5, " Arrows --> here", vec![]),
+            segment(3599.9996, 3725.5, " Later.", vec![]),
+        ]);
+        let options = WriterOptions::default();
+        assert_eq!(
+            transcription.to_format(OutputFormat::Srt, &options),
+            "1\n00:00:00,000 --> 00:00:02,500\nArrows -> here\n\n\
+             2\n01:00:00,000 --> 01:02:05,500\nLater.\n\n"
+        );
+        assert_eq!(
+            transcription.to_format(OutputFormat::Vtt, &options),
+            "WEBVTT\n\n00:00.000 --> 00:02.500\nArrows -> here\n\n\
+             01:00:00.000 --> 01:02:05.500\nLater.\n\n"
+        );
+        assert_eq!(
+            transcription.to_format(OutputFormat::Tsv, &options),
+            "start\tend\ttext\n0\t2500\tArrows --> here\n3600000\t3725500\tLater.\n"
+        );
+        assert_eq!(
+            transcription.to_format(OutputFormat::Txt, &options),
+            "Arrows --> here\nLater.\n"
+        );
+    }
+
+    #[test]
+    fn highlights_words_with_gap_cues() {
+        let transcription = transcription(vec![segment(
+            0.0,
+            1.5,
+            " Hello world",
+            vec![word(" Hello", 0.0, 0.5), word(" world", 1.0, 1.5)],
+        )]);
+        let options = WriterOptions {
+            highlight_words: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            transcription.to_format(OutputFormat::Vtt, &options),
+            "WEBVTT\n\n\
+             00:00.000 --> 00:00.500\n<u>Hello</u> world\n\n\
+             00:00.500 --> 00:01.000\nHello world\n\n\
+             00:01.000 --> 00:01.500\nHello <u>world</u>\n\n"
+        );
+    }
+
+    #[test]
+    fn breaks_lines_and_subtitles() {
+        let words = vec![
+            word(" one", 0.0, 1.0),
+            word(" two", 1.0, 2.0),
+            word(" three", 2.0, 3.0),
+            word(" four", 3.0, 4.0),
+            word(" five", 4.0, 5.0),
+            // A long pause starts a new subtitle.
+            word(" six", 9.0, 10.0),
+        ];
+        let transcription = transcription(vec![segment(
+            0.0,
+            10.0,
+            " one two three four five six",
+            words,
+        )]);
+        let options = WriterOptions {
+            max_line_width: Some(10),
+            max_line_count: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            transcription.to_format(OutputFormat::Srt, &options),
+            "1\n00:00:00,000 --> 00:00:04,000\none two\nthree four\n\n\
+             2\n00:00:04,000 --> 00:00:05,000\nfive\n\n\
+             3\n00:00:09,000 --> 00:00:10,000\nsix\n\n"
+        );
+    }
+
+    #[test]
+    fn limits_words_per_line() {
+        let transcription = transcription(vec![
+            segment(
+                0.0,
+                3.0,
+                " a b c",
+                vec![
+                    word(" a", 0.0, 1.0),
+                    word(" b", 1.0, 2.0),
+                    word(" c", 2.0, 3.0),
+                ],
+            ),
+            segment(3.0, 4.0, " d", vec![word(" d", 3.0, 4.0)]),
+        ]);
+        let options = WriterOptions {
+            max_words_per_line: Some(2),
+            ..Default::default()
+        };
+        // Without a line count segments are preserved, and like the reference
+        // every chunk of words then starts a new subtitle.
+        assert_eq!(
+            transcription.to_format(OutputFormat::Srt, &options),
+            "1\n00:00:00,000 --> 00:00:02,000\na b\n\n\
+             2\n00:00:02,000 --> 00:00:03,000\nc\n\n\
+             3\n00:00:03,000 --> 00:00:04,000\nd\n\n"
+        );
+    }
+
+    #[test]
+    fn writes_json_without_float_noise() {
+        let mut transcription =
+            transcription(vec![segment(1.5, 2.0, " Hi", vec![word(" Hi", 1.5, 2.0)])]);
+        transcription.segments[0].temperature = 0.2;
+        let json: Value = serde_json::from_str(
+            &transcription.to_format(OutputFormat::Json, &WriterOptions::default()),
+        )
+        .unwrap();
+        assert_eq!(json["duration"], json!(12.0));
+        assert_eq!(json["segments"][0]["id"], json!(0));
+        assert_eq!(json["segments"][0]["seek"], json!(150));
+        assert_eq!(json["segments"][0]["temperature"], json!(0.2));
+        assert_eq!(json["segments"][0]["avg_logprob"], json!(-0.25));
+        assert_eq!(json["segments"][0]["words"][0]["word"], json!(" Hi"));
+    }
+}
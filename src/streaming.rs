@@ -0,0 +1,391 @@
+use crate::audio::{self, HOP_LENGTH, SAMPLE_RATE};
+use crate::error::Result;
+use crate::utils::{Segment, TranscribeOptions, Transcription, Word};
+use crate::Whisper;
+
+const MIN_CHUNK_SECONDS: f32 = 1.0;
+const BUFFER_TRIM_SECONDS: f32 = 15.0;
+const MAX_OVERLAP_WORDS: usize = 5;
+
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// Text decoded from the current buffer that may still change.
+    Provisional(Segment),
+    /// Text confirmed by two consecutive decodings; it will not change.
+    Final(Segment),
+}
+
+#[derive(Debug, Clone)]
+struct TimedWord {
+    word: Word,
+    /// Seek of the window the word was decoded from, in stream frames.
+    seek: usize,
+    tokens: Vec<i32>,
+    temperature: f32,
+    avg_logprob: f32,
+    compression_ratio: f32,
+    no_speech_prob: f32,
+}
+
+/// Audio kept before the end of the buffer after a pass without speech, so a
+/// word starting right at the edge isn't cut.
+const SILENCE_MARGIN_SECONDS: f32 = 1.0;
+
+/// The rolling buffer and what has been committed from it; everything
+/// except decoding.
+#[derive(Debug, Default)]
+struct StreamState {
+    buffer: Vec<f32>,
+    buffer_time: f32,
+    unprocessed: usize,
+    committed_tokens: Vec<i32>,
+    recent_words: Vec<String>,
+    last_committed_time: f32,
+    hypothesis: Vec<TimedWord>,
+}
+
+impl StreamState {
+    /// Appends samples and returns whether enough new audio arrived for a pass.
+    fn append(&mut self, samples: &[f32], min_chunk: f32) -> bool {
+        self.buffer.extend_from_slice(samples);
+        self.unprocessed += samples.len();
+        if (self.unprocessed as f32) < min_chunk * SAMPLE_RATE as f32 {
+            return false;
+        }
+        self.unprocessed = 0;
+        true
+    }
+
+    fn buffer_duration(&self) -> f32 {
+        self.buffer.len() as f32 / SAMPLE_RATE as f32
+    }
+
+    fn buffer_end(&self) -> f32 {
+        self.buffer_time + self.buffer_duration()
+    }
+
+    fn trim_buffer(&mut self, time: f32) {
+        let cut = ((time - self.buffer_time) * SAMPLE_RATE as f32).max(0.0) as usize;
+        let cut = cut.min(self.buffer.len());
+        self.buffer.drain(..cut);
+        self.buffer_time += cut as f32 / SAMPLE_RATE as f32;
+    }
+
+    /// Drops the words of a pass that were committed before.
+    fn new_words(&self, words: Vec<TimedWord>) -> Vec<TimedWord> {
+        let mut words: Vec<TimedWord> = words
+            .into_iter()
+            .filter(|word| word.word.start > self.last_committed_time - 0.1)
+            .collect();
+
+        // Drop words repeated from the end of the committed text.
+        if words
+            .first()
+            .is_some_and(|word| (word.word.start - self.last_committed_time).abs() < 1.0)
+        {
+            let max_overlap = MAX_OVERLAP_WORDS
+                .min(self.recent_words.len())
+                .min(words.len());
+            for n in (1..=max_overlap).rev() {
+                let committed = &self.recent_words[self.recent_words.len() - n..];
+                let overlaps = committed
+                    .iter()
+                    .zip(&words[..n])
+                    .all(|(committed, word)| committed == word.word.word.trim());
+                if overlaps {
+                    words.drain(..n);
+                    break;
+                }
+            }
+        }
+
+        words
+    }
+
+    /// Commits the words this pass agrees on with the previous one and keeps
+    /// the rest as the new hypothesis.
+    fn update(
+        &mut self,
+        words: Vec<TimedWord>,
+        buffer_trim: f32,
+        word_timestamps: bool,
+    ) -> Vec<StreamEvent> {
+        let silent = words.is_empty();
+        let agreed = self
+            .hypothesis
+            .iter()
+            .zip(&words)
+            .take_while(|(previous, word)| previous.word.word.trim() == word.word.word.trim())
+            .count();
+        let mut words = words;
+        self.hypothesis = words.split_off(agreed);
+
+        // Never let the buffer grow without bounds when the passes keep disagreeing.
+        if words.is_empty() && self.buffer_duration() > 2.0 * buffer_trim {
+            words = std::mem::take(&mut self.hypothesis);
+        }
+
+        let mut events = vec![];
+        if let Some(segment) = self.commit(words, word_timestamps) {
+            events.push(StreamEvent::Final(segment));
+        }
+        if let Some(segment) = self.segment(&self.hypothesis, word_timestamps) {
+            events.push(StreamEvent::Provisional(segment));
+        }
+
+        if silent {
+            // Nothing to commit during silence or music, so drop the audio
+            // instead of decoding it again on every pass.
+            self.trim_buffer(self.buffer_end() - SILENCE_MARGIN_SECONDS);
+        } else if self.buffer_duration() > buffer_trim {
+            self.trim_buffer(self.last_committed_time);
+        }
+
+        events
+    }
+
+    /// Commits the last words and starts over with an empty buffer.
+    fn finish(&mut self, words: Vec<TimedWord>, word_timestamps: bool) -> Vec<StreamEvent> {
+        let events = self
+            .commit(words, word_timestamps)
+            .map(StreamEvent::Final)
+            .into_iter()
+            .collect();
+
+        self.trim_buffer(self.buffer_end());
+        self.unprocessed = 0;
+        self.hypothesis.clear();
+        self.recent_words.clear();
+        self.committed_tokens.clear();
+        events
+    }
+
+    fn commit(&mut self, words: Vec<TimedWord>, word_timestamps: bool) -> Option<Segment> {
+        let segment = self.segment(&words, word_timestamps)?;
+        self.last_committed_time = segment.end;
+        self.committed_tokens.extend(&segment.tokens);
+        self.recent_words
+            .extend(words.iter().map(|word| word.word.word.trim().to_string()));
+        let excess = self.recent_words.len().saturating_sub(MAX_OVERLAP_WORDS);
+        self.recent_words.drain(..excess);
+        Some(segment)
+    }
+
+    fn segment(&self, words: &[TimedWord], word_timestamps: bool) -> Option<Segment> {
+        let first = words.first()?;
+        Some(Segment {
+            seek: first.seek,
+            start: first.word.start,
+            end: words.last()?.word.end,
+            text: words.iter().map(|word| word.word.word.as_str()).collect(),
+            tokens: words.iter().flat_map(|word| word.tokens.clone()).collect(),
+            temperature: first.temperature,
+            avg_logprob: first.avg_logprob,
+            compression_ratio: first.compression_ratio,
+            no_speech_prob: first.no_speech_prob,
+            words: if word_timestamps {
+                words.iter().map(|word| word.word.clone()).collect()
+            } else {
+                vec![]
+            },
+        })
+    }
+}
+
+/// Transcribes audio as it arrives. Every pass re-decodes a rolling buffer and
+/// commits the words on which the last two passes agree (LocalAgreement-2);
+/// committed audio is then dropped from the buffer.
+pub struct StreamingTranscriber<'a> {
+    whisper: &'a Whisper,
+    language: String,
+    options: TranscribeOptions,
+    min_chunk: f32,
+    buffer_trim: f32,
+    state: StreamState,
+}
+
+impl<'a> StreamingTranscriber<'a> {
+    pub fn new(
+        whisper: &'a Whisper,
+        language: &str,
+        options: TranscribeOptions,
+    ) -> StreamingTranscriber<'a> {
+        StreamingTranscriber {
+            whisper,
+            language: language.to_string(),
+            options,
+            min_chunk: MIN_CHUNK_SECONDS,
+            buffer_trim: BUFFER_TRIM_SECONDS,
+            state: StreamState::default(),
+        }
+    }
+
+    /// Seconds of new audio needed before the buffer is decoded again.
+    pub fn min_chunk(mut self, seconds: f32) -> StreamingTranscriber<'a> {
+        self.min_chunk = seconds;
+        self
+    }
+
+    /// Buffer length in seconds after which committed audio is dropped.
+    pub fn buffer_trim(mut self, seconds: f32) -> StreamingTranscriber<'a> {
+        self.buffer_trim = seconds;
+        self
+    }
+
+    /// Appends 16 kHz mono samples and decodes the buffer once at least
+    /// `min_chunk` seconds of new audio have arrived.
+    pub fn push(&mut self, samples: &[f32]) -> Result<Vec<StreamEvent>> {
+        audio::check_finite(samples)?;
+        if !self.state.append(samples, self.min_chunk) {
+            return Ok(vec![]);
+        }
+        let words = self.decode_buffer()?;
+        let events = self
+            .state
+            .update(words, self.buffer_trim, self.options.word_timestamps);
+        self.clear_prefix(&events);
+        Ok(events)
+    }
+
+    /// Decodes the remaining audio, commits everything and resets the stream.
+    pub fn finish(&mut self) -> Result<Vec<StreamEvent>> {
+        let words = if self.state.buffer.is_empty() {
+            vec![]
+        } else {
+            self.decode_buffer()?
+        };
+        let events = self.state.finish(words, self.options.word_timestamps);
+        self.clear_prefix(&events);
+        Ok(events)
+    }
+
+    /// The prefix belongs to the start of the stream only.
+    fn clear_prefix(&mut self, events: &[StreamEvent]) {
+        if events
+            .iter()
+            .any(|event| matches!(event, StreamEvent::Final(_)))
+        {
+            self.options.prefix = None;
+        }
+    }
+
+    /// Decodes the buffer and returns the words that are not committed yet.
+    fn decode_buffer(&mut self) -> Result<Vec<TimedWord>> {
+        let transcription = self.whisper.transcribe_audio(
+            &self.state.buffer,
+            &self.language,
+            &self.options,
+            self.state.committed_tokens.clone(),
+        )?;
+        // Keep the detected language so later passes don't re-detect it.
+        self.language = transcription.language.clone();
+
+        let words = self.timed_words(&transcription);
+        Ok(self.state.new_words(words))
+    }
+
+    fn timed_words(&self, transcription: &Transcription) -> Vec<TimedWord> {
+        let eot_token = self.whisper.options.eot_token as i32;
+        let buffer_time = self.state.buffer_time;
+        let buffer_frames = (buffer_time * (SAMPLE_RATE / HOP_LENGTH) as f32).round() as usize;
+        let mut timed_words = vec![];
+        for segment in &transcription.segments {
+            let text_tokens: Vec<i32> = segment
+                .tokens
+                .iter()
+                .copied()
+                .filter(|&token| token < eot_token)
+                .collect();
+            let (words, word_tokens) = self.whisper.split_words(&text_tokens, &self.language);
+
+            // Without aligned words, spread the segment evenly over its tokens.
+            let aligned = segment.words.len() == words.len();
+            let duration = (segment.end - segment.start) / text_tokens.len().max(1) as f32;
+            let mut position = 0;
+            for (i, (word, tokens)) in words.into_iter().zip(word_tokens).enumerate() {
+                let word = if aligned {
+                    segment.words[i].clone()
+                } else {
+                    Word {
+                        word,
+                        start: segment.start + position as f32 * duration,
+                        end: segment.start + (position + tokens.len()) as f32 * duration,
+                        probability: segment.avg_logprob.exp(),
+                    }
+                };
+                position += tokens.len();
+                timed_words.push(TimedWord {
+                    word: Word {
+                        start: buffer_time + word.start,
+                        end: buffer_time + word.end,
+                        ..word
+                    },
+                    seek: buffer_frames + segment.seek,
+                    tokens,
+                    temperature: segment.temperature,
+                    avg_logprob: segment.avg_logprob,
+                    compression_ratio: segment.compression_ratio,
+                    no_speech_prob: segment.no_speech_prob,
+                });
+            }
+        }
+        timed_words
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str, start: f32, end: f32) -> TimedWord {
+        TimedWord {
+            word: Word {
+                word: text.to_string(),
+                start,
+                end,
+                probability: 1.0,
+            },
+            seek: 0,
+            tokens: vec![],
+            temperature: 0.0,
+            avg_logprob: 0.0,
+            compression_ratio: 1.0,
+            no_speech_prob: 0.0,
+        }
+    }
+
+    #[test]
+    fn silence_keeps_the_buffer_bounded() {
+        let mut state = StreamState::default();
+        let second = vec![0.0; SAMPLE_RATE];
+        // Ten minutes of silence, one pass per second.
+        for _ in 0..600 {
+            assert!(state.append(&second, MIN_CHUNK_SECONDS));
+            let events = state.update(vec![], BUFFER_TRIM_SECONDS, false);
+            assert!(events.is_empty());
+            assert!(state.buffer_duration() <= MIN_CHUNK_SECONDS + SILENCE_MARGIN_SECONDS);
+        }
+        assert!((state.buffer_end() - 600.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn agreed_words_are_committed_and_trimmed() {
+        let mut state = StreamState::default();
+        state.append(&vec![0.0; 20 * SAMPLE_RATE], MIN_CHUNK_SECONDS);
+
+        let events = state.update(vec![word(" Hello", 0.5, 1.0)], BUFFER_TRIM_SECONDS, false);
+        assert!(matches!(&events[..], [StreamEvent::Provisional(_)]));
+
+        let words = vec![word(" Hello", 0.5, 1.0), word(" there", 1.0, 1.5)];
+        let events = state.update(words, BUFFER_TRIM_SECONDS, false);
+        match &events[..] {
+            [StreamEvent::Final(done), StreamEvent::Provisional(pending)] => {
+                assert_eq!(done.text, " Hello");
+                assert_eq!(pending.text, " there");
+            }
+            events => panic!("unexpected events: {:?}", events),
+        }
+        // The buffer is longer than `buffer_trim`, so committed audio is dropped.
+        assert!((state.buffer_time - 1.0).abs() < 1e-3);
+    }
+}
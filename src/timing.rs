@@ -0,0 +1,271 @@
+use tract_onnx::tract_hir::tract_ndarray::{s, Array1, Array2, Array3, Axis};
+
+pub const TOKENS_PER_SECOND: f32 = 50.0;
+const MEDIAN_FILTER_WIDTH: usize = 7;
+const PUNCTUATION: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// Normalizes the attention of every head over tokens, smooths it over frames
+/// and averages the heads into a single token-by-frame matrix.
+pub fn alignment_matrix(weights: Array3<f32>, num_frames: usize) -> Array2<f32> {
+    let num_frames = num_frames.min(weights.shape()[2]);
+    let mut weights = weights.slice(s![.., .., ..num_frames]).to_owned();
+
+    for mut head in weights.outer_iter_mut() {
+        for row in 0..head.shape()[0] {
+            let mut row = head.row_mut(row);
+            let max = row.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+            row.mapv_inplace(|x| (x - max).exp());
+            let sum = row.sum();
+            row.mapv_inplace(|x| x / sum);
+        }
+        for mut column in head.columns_mut() {
+            let mean = column.mean().unwrap_or(0.0);
+            let std = column.std(0.0).max(1e-10);
+            column.mapv_inplace(|x| (x - mean) / std);
+        }
+        for mut row in head.rows_mut() {
+            let filtered = median_filter(&row.to_vec(), MEDIAN_FILTER_WIDTH);
+            row.assign(&Array1::from(filtered));
+        }
+    }
+
+    weights.mean_axis(Axis(0)).unwrap()
+}
+
+fn median_filter(values: &[f32], width: usize) -> Vec<f32> {
+    let pad = width / 2;
+    if values.len() <= pad {
+        return values.to_vec();
+    }
+
+    // Reflect padding, as in the reference implementation.
+    let padded: Vec<f32> = (0..values.len() + 2 * pad)
+        .map(|i| {
+            let i = i as isize - pad as isize;
+            let i = if i < 0 {
+                -i
+            } else if i >= values.len() as isize {
+                2 * (values.len() as isize - 1) - i
+            } else {
+                i
+            };
+            values[i as usize]
+        })
+        .collect();
+
+    padded
+        .windows(width)
+        .map(|window| {
+            let mut window = window.to_vec();
+            window.sort_by(|a, b| a.total_cmp(b));
+            window[pad]
+        })
+        .collect()
+}
+
+/// Finds the cheapest monotonic path through `cost` and returns the token and
+/// frame index of every step.
+pub fn dtw(cost: &Array2<f32>) -> (Vec<usize>, Vec<usize>) {
+    let (n, m) = (cost.shape()[0], cost.shape()[1]);
+    let mut total = Array2::from_elem((n + 1, m + 1), f32::INFINITY);
+    let mut trace = Array2::from_elem((n + 1, m + 1), 0u8);
+    total[[0, 0]] = 0.0;
+
+    for j in 1..=m {
+        for i in 1..=n {
+            let c0 = total[[i - 1, j - 1]];
+            let c1 = total[[i - 1, j]];
+            let c2 = total[[i, j - 1]];
+
+            let (c, t) = if c0 < c1 && c0 < c2 {
+                (c0, 0)
+            } else if c1 < c0 && c1 < c2 {
+                (c1, 1)
+            } else {
+                (c2, 2)
+            };
+            total[[i, j]] = cost[[i - 1, j - 1]] + c;
+            trace[[i, j]] = t;
+        }
+    }
+
+    for j in 0..=m {
+        trace[[0, j]] = 2;
+    }
+    for i in 0..=n {
+        trace[[i, 0]] = 1;
+    }
+
+    let (mut i, mut j) = (n, m);
+    let mut path = vec![];
+    while i > 0 || j > 0 {
+        path.push((i.saturating_sub(1), j.saturating_sub(1)));
+        match trace[[i, j]] {
+            0 => {
+                i -= 1;
+                j -= 1;
+            }
+            1 => i -= 1,
+            _ => j -= 1,
+        }
+    }
+    path.reverse();
+
+    path.into_iter().unzip()
+}
+
+/// Returns, for every row of the DTW path, the time at which it was first reached.
+pub fn jump_times(text_indices: &[usize], time_indices: &[usize]) -> Vec<f32> {
+    (0..text_indices.len())
+        .filter(|&i| i == 0 || text_indices[i] != text_indices[i - 1])
+        .map(|i| time_indices[i] as f32 / TOKENS_PER_SECOND)
+        .collect()
+}
+
+/// Groups text tokens into words. Languages written without spaces are split
+/// at every complete unicode character instead.
+pub fn split_to_word_tokens(
+    tokens: &[i32],
+    decode: impl Fn(&[i32]) -> String,
+    split_on_spaces: bool,
+) -> (Vec<String>, Vec<Vec<i32>>) {
+    let mut subwords = vec![];
+    let mut subword_tokens = vec![];
+    let mut current = vec![];
+    for &token in tokens {
+        current.push(token);
+        let decoded = decode(&current);
+        if !decoded.contains('\u{fffd}') {
+            subwords.push(decoded);
+            subword_tokens.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        subwords.push(decode(&current));
+        subword_tokens.push(current);
+    }
+
+    if !split_on_spaces {
+        return (subwords, subword_tokens);
+    }
+
+    let mut words: Vec<String> = vec![];
+    let mut word_tokens: Vec<Vec<i32>> = vec![];
+    for (subword, tokens) in subwords.into_iter().zip(subword_tokens) {
+        let trimmed = subword.trim();
+        // A substring test like the reference, so "" and "()" count as well.
+        let punctuation = PUNCTUATION.contains(trimmed);
+        if words.is_empty() || subword.starts_with(' ') || punctuation {
+            words.push(subword);
+            word_tokens.push(tokens);
+        } else {
+            words.last_mut().unwrap().push_str(&subword);
+            word_tokens.last_mut().unwrap().extend(tokens);
+        }
+    }
+
+    (words, word_tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tract_onnx::tract_hir::tract_ndarray::{arr2, Array3};
+
+    #[test]
+    fn median_filter_reflects_at_the_edges() {
+        let values = [1.0, 9.0, 2.0, 8.0, 3.0, 7.0, 4.0, 6.0, 5.0];
+        assert_eq!(
+            median_filter(&values, 7),
+            [8.0, 3.0, 7.0, 4.0, 6.0, 5.0, 6.0, 5.0, 6.0]
+        );
+        assert_eq!(
+            median_filter(&[5.0, 1.0, 4.0, 2.0], 3),
+            [1.0, 4.0, 2.0, 4.0]
+        );
+        // Too short to pad: returned unchanged.
+        assert_eq!(median_filter(&[3.0, 1.0, 2.0], 7), [3.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn alignment_matrix_matches_reference() {
+        let weights = Array3::from_shape_fn((2, 3, 10), |(h, t, f)| {
+            let x = (h + 1) as f32 * (t as f32 * 1.3 + f as f32 * 0.7).sin();
+            x + if f == 2 * t + h { 2.0 } else { 0.0 }
+        });
+        let expected = arr2(&[
+            [
+                0.69673, 0.69673, 0.69673, 0.02454, -0.13115, -0.56614, -0.56169, -0.56169,
+            ],
+            [
+                0.63856, 0.63856, -0.36185, -0.40251, -0.60126, -0.67274, -0.67274, -0.83688,
+            ],
+            [
+                -1.1212, -1.05342, -0.98106, -0.65721, 0.32472, 1.39546, 1.39789, 1.39789,
+            ],
+        ]);
+        let matrix = alignment_matrix(weights, 8);
+        assert_eq!(matrix.shape(), expected.shape());
+        for (value, expected) in matrix.iter().zip(&expected) {
+            assert!((value - expected).abs() < 1e-4, "{} != {}", value, expected);
+        }
+    }
+
+    #[test]
+    fn dtw_matches_reference() {
+        let cost = arr2(&[
+            [0.0, 1.0, 1.0, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [1.0, 1.0, 1.0, 0.0],
+        ]);
+        assert_eq!(dtw(&cost), (vec![0, 1, 1, 2], vec![0, 1, 2, 3]));
+
+        let cost = arr2(&[[0.0, 0.0, 0.0], [9.0, 9.0, 0.0]]);
+        assert_eq!(dtw(&cost), (vec![0, 1, 1, 1], vec![0, 0, 1, 2]));
+
+        let cost = arr2(&[[5.0, 0.0, 0.0], [0.0, 9.0, 9.0], [0.0, 9.0, 9.0]]);
+        assert_eq!(dtw(&cost), (vec![0, 1, 2, 2, 2], vec![0, 0, 0, 1, 2]));
+    }
+
+    #[test]
+    fn jump_times_mark_the_first_frame_of_every_token() {
+        assert_eq!(jump_times(&[0, 1, 1, 2], &[0, 1, 2, 3]), [0.0, 0.02, 0.06]);
+        assert_eq!(jump_times(&[0, 1, 1, 1], &[0, 0, 1, 2]), [0.0, 0.0]);
+    }
+
+    fn decode(tokens: &[i32]) -> String {
+        tokens
+            .iter()
+            .map(|&token| match token {
+                1 => " Hello",
+                2 => ",",
+                3 => " wor",
+                4 => "ld",
+                5 => "!",
+                6 => "é",
+                7 => "()",
+                _ => "",
+            })
+            .collect::<String>()
+            // Token 10 is the first half of a character that token 11 completes.
+            + match (tokens.contains(&10), tokens.contains(&11)) {
+                (true, false) => "\u{fffd}",
+                (true, true) => "日",
+                _ => "",
+            }
+    }
+
+    #[test]
+    fn split_to_word_tokens_merges_subwords() {
+        let (words, tokens) = split_to_word_tokens(&[1, 2, 3, 4, 7, 5], decode, true);
+        assert_eq!(words, [" Hello", ",", " world", "()", "!"]);
+        assert_eq!(tokens, [vec![1], vec![2], vec![3, 4], vec![7], vec![5]]);
+    }
+
+    #[test]
+    fn split_to_word_tokens_keeps_characters_whole() {
+        let (words, tokens) = split_to_word_tokens(&[10, 11, 6], decode, false);
+        assert_eq!(words, ["日", "é"]);
+        assert_eq!(tokens, [vec![10, 11], vec![6]]);
+    }
+}
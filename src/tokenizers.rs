@@ -1,3 +1,4 @@
+use crate::error::{Error, Result};
 use base64::{engine::general_purpose, Engine as _};
 use rustc_hash::FxHashMap as HashMap;
 use std::fs::File;
@@ -8,11 +9,15 @@ use tiktoken_rs::CoreBPE;
 pub struct Tokenizer {
     bpe: CoreBPE,
     pub lang2token: HashMap<String, usize>,
+    special_tokens: HashMap<String, usize>,
+    /// The bytes of every token, so segments can end mid-character.
+    decoder: HashMap<usize, Vec<u8>>,
+    vocab_size: usize,
 }
 
 impl Tokenizer {
-    pub fn new(vocab_path: &str) -> Tokenizer {
-        let mut file = File::open(vocab_path).expect("Не удалось открыть файл");
+    pub fn new(vocab_path: &str, num_languages: usize) -> Result<Tokenizer> {
+        let mut file = File::open(vocab_path)?;
 
         let langs = vec![
             ("en", "english"),
@@ -114,19 +119,26 @@ impl Tokenizer {
             ("ba", "bashkir"),
             ("jw", "javanese"),
             ("su", "sundanese"),
+            ("yue", "cantonese"),
         ];
-        let languages: HashMap<&str, &str> = langs.iter().cloned().collect();
+        let langs = &langs[..num_languages.min(langs.len())];
 
         let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Не удалось прочитать файл");
+        file.read_to_string(&mut contents)?;
 
         let mut encoder = HashMap::default();
 
         for line in contents.lines() {
             let mut parts = line.split(' ');
-            let word: String = parts.next().unwrap().parse().unwrap();
-            let rank: usize = parts.next().unwrap().parse().unwrap();
+            let (word, rank) = match (parts.next(), parts.next().map(str::parse::<usize>)) {
+                (Some(word), Some(Ok(rank))) => (word, rank),
+                _ => {
+                    return Err(Error::Tokenizer(format!(
+                        "invalid vocabulary line: {}",
+                        line
+                    )))
+                }
+            };
 
             let token = &general_purpose::STANDARD.decode(word);
 
@@ -141,15 +153,15 @@ impl Tokenizer {
             }
         }
 
-        let mut special_tokens = HashMap::default();
-        let n_vocab: usize = encoder.len();
+        let mut special_tokens: HashMap<String, usize> = HashMap::default();
+        let n_vocab: usize = encoder.values().max().map_or(0, |rank| rank + 1);
 
         let mut specials = vec![
             "<|endoftext|>".to_string(),
             "<|startoftranscript|>".to_string(),
         ];
 
-        for lang in languages.keys() {
+        for (lang, _) in langs {
             specials.push(format!("<|{}|>", lang));
         }
         specials.extend(vec![
@@ -165,35 +177,125 @@ impl Tokenizer {
             specials.push(formatted);
         }
 
+        let vocab_size = n_vocab + specials.len();
         for (index, value) in specials.iter().enumerate() {
-            special_tokens.insert(value.into(), index + n_vocab + 1);
+            special_tokens.insert(value.into(), index + n_vocab);
         }
 
+        let decoder = encoder
+            .iter()
+            .map(|(bytes, &rank)| (rank, bytes.clone()))
+            .chain(
+                special_tokens
+                    .iter()
+                    .map(|(token, &rank)| (rank, token.clone().into_bytes())),
+            )
+            .collect();
         let bpe = CoreBPE::new(
             encoder,
-            special_tokens,
+            special_tokens.clone(),
             "'s|'t|'re|'ve|'m|'ll|'d| ?\\p{L}+| ?\\p{N}+| ?[^\\s\\p{L}\\p{N}]+|\\s+(?!\\S)|\\s+",
         )
-        .unwrap();
+        .map_err(|err| Error::Tokenizer(err.to_string()))?;
 
         let lang2token: HashMap<String, usize> = langs
             .iter()
-            .enumerate()
-            .map(|(index, pairs)| {
+            .map(|(lang, _)| {
                 (
-                    String::from(pairs.0),
-                    bpe.encode_with_special_tokens("<|startoftranscript|>")[0] + index,
+                    String::from(*lang),
+                    special_tokens[&format!("<|{}|>", lang)],
                 )
             })
             .collect();
-        Tokenizer { bpe, lang2token }
+        Ok(Tokenizer {
+            bpe,
+            lang2token,
+            special_tokens,
+            decoder,
+            vocab_size,
+        })
+    }
+
+    /// Number of token ids: regular tokens, special tokens and timestamps.
+    pub fn vocab_size(&self) -> usize {
+        self.vocab_size
+    }
+
+    /// Returns the id of a special token such as `<|endoftext|>`.
+    pub fn special_token(&self, token: &str) -> Option<usize> {
+        self.special_tokens.get(token).copied()
     }
 
-    // pub fn encode(&self, text: &str) -> Vec<usize> {
-    //     self.bpe.encode_with_special_tokens(text)
-    // }
+    /// Encodes plain text; special token markup such as `<|en|>` is treated as
+    /// ordinary text.
+    pub fn encode(&self, text: &str) -> Vec<usize> {
+        self.bpe.encode_ordinary(text)
+    }
 
     pub fn decode(&self, tokens: Vec<usize>) -> String {
-        self.bpe.decode(tokens).unwrap()
+        // A segment may end in the middle of a multi-byte character.
+        let bytes: Vec<u8> = tokens
+            .iter()
+            .filter_map(|token| self.decoder.get(token))
+            .flatten()
+            .copied()
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A vocabulary of the 256 single bytes, written to a temporary file.
+    fn byte_tokenizer(name: &str, num_languages: usize) -> Tokenizer {
+        let path = std::env::temp_dir().join(format!(
+            "rusty-whisper-{}-{}.tiktoken",
+            name,
+            std::process::id()
+        ));
+        let mut file = File::create(&path).unwrap();
+        for byte in 0..=255u8 {
+            writeln!(
+                file,
+                "{} {}",
+                general_purpose::STANDARD.encode([byte]),
+                byte
+            )
+            .unwrap();
+        }
+        drop(file);
+        let tokenizer = Tokenizer::new(&path.to_string_lossy(), num_languages).unwrap();
+        std::fs::remove_file(path).unwrap();
+        tokenizer
+    }
+
+    #[test]
+    fn vocab_size_counts_special_tokens() {
+        // 2 start tokens, 6 task and control tokens and 1501 timestamps.
+        assert_eq!(byte_tokenizer("english", 99).vocab_size(), 256 + 1608);
+        assert_eq!(byte_tokenizer("v3", 100).vocab_size(), 256 + 1609);
+    }
+
+    #[test]
+    fn looks_up_special_tokens() {
+        let tokenizer = byte_tokenizer("special", 99);
+        assert_eq!(tokenizer.special_token("<|endoftext|>"), Some(256));
+        assert_eq!(tokenizer.special_token("<|en|>"), Some(258));
+        assert_eq!(tokenizer.lang2token["en"], 258);
+        assert_eq!(tokenizer.special_token("<|0.00|>"), Some(256 + 107));
+        assert_eq!(tokenizer.special_token(""), None);
+        assert_eq!(tokenizer.special_token("a"), None);
+    }
+
+    #[test]
+    fn decodes_partial_characters() {
+        let tokenizer = byte_tokenizer("decode", 99);
+        let tokens = tokenizer.encode(" é!");
+        assert_eq!(tokenizer.decode(tokens.clone()), " é!");
+        assert_eq!(tokenizer.decode(tokens[..2].to_vec()), " \u{fffd}");
+        assert_eq!(tokenizer.decode(vec![256, 104, 105]), "<|endoftext|>hi");
     }
 }
@@ -1,57 +1,296 @@
+use crate::audio::ChannelMode;
+use crate::error::{Error, Result};
+use crate::filters::LogitFilter;
+use crate::tokenizers::Tokenizer;
+use crate::vad::VadOptions;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+use std::sync::Arc;
 use tract_ndarray::{Array3, Dim};
 use tract_onnx::prelude::*;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ModelKind {
+    #[default]
+    Multilingual,
+    /// English-only `.en` models: GPT-2 vocabulary, no language or task tokens.
+    English,
+}
+
 #[derive(Debug)]
 pub struct Options {
+    pub model_kind: ModelKind,
     pub eot_token: usize,
+    pub sot_token: usize,
     pub sot_prev: usize,
+    pub transcribe_token: usize,
+    pub translate_token: usize,
+    pub no_timestamps_token: usize,
+    pub no_speech_token: usize,
+    pub timestamp_begin: usize,
+    pub blank_tokens: Vec<usize>,
+    /// Non-speech symbols and the special tokens that must never be sampled.
+    pub suppress_tokens: Vec<usize>,
     pub n_ctx: usize,
+    pub n_text_state: usize,
+    pub n_text_layer: usize,
+    pub sample_len: usize,
 }
 
 impl Options {
-    pub fn new() -> Options {
-        Options {
-            eot_token: 50257,
-            sot_prev: 50361,
-            n_ctx: 448,
+    pub fn new(
+        tokenizer: &Tokenizer,
+        model_kind: ModelKind,
+        n_ctx: usize,
+        n_text_state: usize,
+        n_text_layer: usize,
+    ) -> Result<Options> {
+        let special = |token: &str| {
+            tokenizer
+                .special_token(token)
+                .ok_or_else(|| Error::Tokenizer(format!("missing special token {}", token)))
+        };
+        Ok(Options {
+            model_kind,
+            eot_token: special("<|endoftext|>")?,
+            sot_token: special("<|startoftranscript|>")?,
+            sot_prev: special("<|startofprev|>")?,
+            transcribe_token: special("<|transcribe|>")?,
+            translate_token: special("<|translate|>")?,
+            no_timestamps_token: special("<|notimestamps|>")?,
+            no_speech_token: special("<|nospeech|>")?,
+            timestamp_begin: special("<|0.00|>")?,
+            blank_tokens: tokenizer.encode(" "),
+            suppress_tokens: suppress_tokens(tokenizer),
+            n_ctx,
+            n_text_state,
+            n_text_layer,
+            sample_len: n_ctx / 2,
+        })
+    }
+}
+
+/// Symbols that are rarely spoken, such as brackets and music notes, plus the
+/// special tokens, following the reference implementation.
+fn suppress_tokens(tokenizer: &Tokenizer) -> Vec<usize> {
+    let miscellaneous = "♩♪♫♬♭♮♯";
+    let symbols = "\"#()*+/:;<=>@[\\]^_`{|}~「」『』"
+        .chars()
+        .map(String::from)
+        .chain(
+            "<< >> <<< >>> -- --- -( -[ (' (\" (( )) ((( ))) [[ ]] {{ }} ♪♪ ♪♪♪"
+                .split(' ')
+                .map(String::from),
+        );
+
+    let mut tokens = vec![tokenizer.encode(" -")[0], tokenizer.encode(" '")[0]];
+    for symbol in symbols {
+        for encoded in [
+            tokenizer.encode(&symbol),
+            tokenizer.encode(&format!(" {}", symbol)),
+        ] {
+            if encoded.len() == 1 {
+                tokens.push(encoded[0]);
+            }
+        }
+    }
+    for symbol in miscellaneous.chars() {
+        tokens.push(tokenizer.encode(&symbol.to_string())[0]);
+        tokens.push(tokenizer.encode(&format!(" {}", symbol))[0]);
+    }
+    for special in [
+        "<|transcribe|>",
+        "<|translate|>",
+        "<|startoftranscript|>",
+        "<|startofprev|>",
+        "<|startoflm|>",
+        "<|nospeech|>",
+    ] {
+        tokens.extend(tokenizer.special_token(special));
+    }
+
+    tokens.sort_unstable();
+    tokens.dedup();
+    tokens
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Task {
+    #[default]
+    Transcribe,
+    /// Translate speech in any language to English text.
+    Translate,
+}
+
+/// Whether the text of earlier windows is used as the prompt for the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Conditioning {
+    Always,
+    /// Only the first window sees `initial_prompt`.
+    Never,
+    /// Drop the earlier text after a window that needed temperature fallback or
+    /// exceeded the compression ratio threshold, so it can't poison later ones.
+    #[default]
+    ResetOnFallback,
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscribeOptions {
+    pub task: Task,
+    /// How multi-channel audio files are reduced to mono.
+    pub channel_mode: ChannelMode,
+    /// Number of beams; `None` or `Some(1)` decodes greedily.
+    pub beam_size: Option<usize>,
+    /// Beam search stops once `beam_size * patience` hypotheses have finished.
+    pub patience: f32,
+    /// Exponent of the length penalty used to rank finished hypotheses;
+    /// `None` ranks them by average log-probability per token.
+    pub length_penalty: Option<f32>,
+    /// Temperatures tried in order until a decoding passes the thresholds below.
+    pub temperatures: Vec<f32>,
+    /// Number of samples drawn at non-zero temperatures, keeping the best one.
+    pub best_of: Option<usize>,
+    /// Re-decode when the text compresses better than this ratio, a sign of repetition.
+    pub compression_ratio_threshold: Option<f32>,
+    /// Re-decode when the average log-probability of the tokens is below this value.
+    pub logprob_threshold: Option<f32>,
+    /// Skip a window as silent when its no-speech probability exceeds this value
+    /// and its average log-probability is below `logprob_threshold`.
+    pub no_speech_threshold: Option<f32>,
+    pub conditioning: Conditioning,
+    /// Keep windows from starting with a blank or ending before any text.
+    pub suppress_blank: bool,
+    /// Token ids that are never sampled; `None` suppresses non-speech symbols
+    /// such as brackets and music notes.
+    pub suppress_tokens: Option<Vec<i32>>,
+    /// Latest allowed first timestamp of a window, in seconds.
+    pub max_initial_timestamp: Option<f32>,
+    /// Applied after the built-in filters, e.g. to enforce domain constraints.
+    pub logit_filters: Vec<Arc<dyn LogitFilter>>,
+    /// Text placed before the first window as if it had been spoken earlier,
+    /// biasing the vocabulary and style, e.g. towards product names.
+    pub initial_prompt: Option<String>,
+    /// Text forced at the start of the first window's transcription.
+    pub prefix: Option<String>,
+    /// Seed of the sampling RNG; `None` seeds it from system entropy.
+    pub seed: Option<u64>,
+    /// Align every word to the audio; requires `Whisper::with_alignment_model`.
+    pub word_timestamps: bool,
+    /// Only decode the speech regions found by voice activity detection.
+    pub vad: Option<VadOptions>,
+}
+
+impl Default for TranscribeOptions {
+    fn default() -> TranscribeOptions {
+        TranscribeOptions {
+            task: Task::Transcribe,
+            channel_mode: ChannelMode::Downmix,
+            beam_size: None,
+            patience: 1.0,
+            length_penalty: None,
+            temperatures: vec![0.0, 0.2, 0.4, 0.6, 0.8, 1.0],
+            best_of: Some(5),
+            compression_ratio_threshold: Some(2.4),
+            logprob_threshold: Some(-1.0),
+            no_speech_threshold: Some(0.6),
+            conditioning: Conditioning::ResetOnFallback,
+            suppress_blank: true,
+            suppress_tokens: None,
+            max_initial_timestamp: Some(1.0),
+            logit_filters: vec![],
+            initial_prompt: None,
+            prefix: None,
+            seed: None,
+            word_timestamps: false,
+            vad: None,
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct DecodingResult {
+    pub tokens: Vec<i32>,
+    pub avg_logprob: f32,
+    pub compression_ratio: f32,
+    pub no_speech_prob: f32,
+    pub temperature: f32,
+}
+
+pub fn log_softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits.iter().map(|x| (x - max).exp()).sum::<f32>().ln() + max;
+    logits.iter().map(|x| x - log_sum).collect()
+}
+
+pub fn compression_ratio(text: &str) -> f32 {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(text.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    text.len() as f32 / compressed.len() as f32
+}
+
+pub fn sequence_score(length: usize, sum_logprob: f32, length_penalty: Option<f32>) -> f32 {
+    let penalty = match length_penalty {
+        Some(alpha) => ((5.0 + length as f32) / 6.0).powf(alpha),
+        None => length.max(1) as f32,
+    };
+    sum_logprob / penalty
+}
+
 #[derive(Debug, Clone)]
 pub struct KVCache {
-    pub k1: Tensor,
-    pub k2: Tensor,
-    pub k3: Tensor,
-    pub k4: Tensor,
-    pub k5: Tensor,
-    pub k6: Tensor,
-    pub v1: Tensor,
-    pub v2: Tensor,
-    pub v3: Tensor,
-    pub v4: Tensor,
-    pub v5: Tensor,
-    pub v6: Tensor,
+    pub layers: Vec<(Tensor, Tensor)>,
 }
 
 impl KVCache {
-    pub fn default() -> KVCache {
-        let shape = Dim([1, 0, 512]);
-        let value: Array3<f32> = Array3::zeros(shape);
+    pub fn new(n_layers: usize, n_state: usize) -> KVCache {
+        let shape = Dim([1, 0, n_state]);
+        let value = Array3::<f32>::zeros(shape).into_tensor();
 
         KVCache {
-            k1: value.clone().into_tensor(),
-            k2: value.clone().into_tensor(),
-            k3: value.clone().into_tensor(),
-            k4: value.clone().into_tensor(),
-            k5: value.clone().into_tensor(),
-            k6: value.clone().into_tensor(),
-            v1: value.clone().into_tensor(),
-            v2: value.clone().into_tensor(),
-            v3: value.clone().into_tensor(),
-            v4: value.clone().into_tensor(),
-            v5: value.clone().into_tensor(),
-            v6: value.clone().into_tensor(),
+            layers: vec![(value.clone(), value); n_layers],
         }
     }
+
+    pub fn len(&self) -> usize {
+        self.layers.first().map_or(0, |(k, _)| k.shape()[1])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Word {
+    /// Word text, including its leading space.
+    pub word: String,
+    pub start: f32,
+    pub end: f32,
+    /// Average probability of the word's tokens.
+    pub probability: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    /// Mel frame (10 ms) at which the window this segment was decoded from starts.
+    pub seek: usize,
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+    pub tokens: Vec<i32>,
+    pub temperature: f32,
+    pub avg_logprob: f32,
+    pub compression_ratio: f32,
+    /// Probability of the no-speech token at the start of the window.
+    pub no_speech_prob: f32,
+    /// Empty unless `TranscribeOptions::word_timestamps` is set.
+    pub words: Vec<Word>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Transcription {
+    pub text: String,
+    pub segments: Vec<Segment>,
+    /// Language code the audio was decoded with, detected when "auto" was requested.
+    pub language: String,
+    /// Length of the audio in seconds.
+    pub duration: f32,
 }
@@ -0,0 +1,375 @@
+use crate::audio::SAMPLE_RATE;
+use crate::error::Result;
+use crate::Model;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::ops::Range;
+use tract_ndarray::{concatenate, s, Array, Array3, Axis};
+use tract_onnx::prelude::*;
+
+pub const ENERGY_FRAME: usize = 480;
+const ENERGY_FFT: usize = 512;
+const FLUX_SMOOTHING: usize = 2;
+const ENERGY_FLOOR_PERCENTILE: f32 = 0.1;
+const ENERGY_FLOOR_MAX_DB: f32 = -40.0;
+pub const SILERO_FRAME: usize = 512;
+const SILERO_CONTEXT: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct VadOptions {
+    /// Speech probability above which a frame counts as speech (Silero model).
+    pub threshold: f32,
+    /// Decibels above the estimated noise floor a frame needs to count as speech
+    /// (energy detector).
+    pub energy_threshold: f32,
+    /// Minimum spectral flux, which rejects loud but steady sounds such as hum
+    /// (energy detector).
+    pub flux_threshold: f32,
+    /// Speech regions shorter than this many seconds are dropped.
+    pub min_speech_duration: f32,
+    /// Pauses shorter than this many seconds don't end a speech region.
+    pub min_silence_duration: f32,
+    /// Seconds of audio kept on both sides of every speech region.
+    pub speech_pad: f32,
+}
+
+impl Default for VadOptions {
+    fn default() -> VadOptions {
+        VadOptions {
+            threshold: 0.5,
+            energy_threshold: 10.0,
+            flux_threshold: 0.05,
+            min_speech_duration: 0.25,
+            min_silence_duration: 0.5,
+            speech_pad: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeechRegion {
+    pub start: f32,
+    pub end: f32,
+}
+
+fn seconds_to_samples(seconds: f32) -> usize {
+    (seconds.max(0.0) * SAMPLE_RATE as f32) as usize
+}
+
+/// Marks frames that are well above the noise floor and spectrally changing.
+pub fn energy_speech_probs(samples: &[f32], options: &VadOptions) -> Vec<f32> {
+    let fft = FftPlanner::new().plan_fft_forward(ENERGY_FFT);
+    let window: Vec<f32> = (0..ENERGY_FRAME)
+        .map(|n| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / ENERGY_FRAME as f32).cos())
+        .collect();
+
+    let mut energies = vec![];
+    let mut fluxes = vec![];
+    let mut previous: Option<Vec<f32>> = None;
+    for frame in samples.chunks(ENERGY_FRAME) {
+        let power = frame.iter().map(|x| x * x).sum::<f32>() / frame.len() as f32;
+        energies.push(10.0 * (power + 1e-10).log10());
+
+        let mut spectrum = vec![Complex::new(0.0, 0.0); ENERGY_FFT];
+        for (i, (x, w)) in frame.iter().zip(&window).enumerate() {
+            spectrum[i] = Complex::new(x * w, 0.0);
+        }
+        fft.process(&mut spectrum);
+        let magnitudes: Vec<f32> = spectrum[..ENERGY_FFT / 2 + 1]
+            .iter()
+            .map(|c| c.norm())
+            .collect();
+        let norm = magnitudes
+            .iter()
+            .map(|m| m * m)
+            .sum::<f32>()
+            .sqrt()
+            .max(1e-10);
+        let magnitudes: Vec<f32> = magnitudes.iter().map(|m| m / norm).collect();
+
+        let flux = match &previous {
+            Some(previous) => magnitudes
+                .iter()
+                .zip(previous)
+                .map(|(m, p)| (m - p).max(0.0).powi(2))
+                .sum::<f32>()
+                .sqrt(),
+            None => 0.0,
+        };
+        fluxes.push(flux);
+        previous = Some(magnitudes);
+    }
+    if energies.is_empty() {
+        return vec![];
+    }
+
+    let mut sorted = energies.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let floor = sorted[((sorted.len() - 1) as f32 * ENERGY_FLOOR_PERCENTILE) as usize]
+        .min(ENERGY_FLOOR_MAX_DB);
+
+    (0..energies.len())
+        .map(|i| {
+            let neighbours = &fluxes
+                [i.saturating_sub(FLUX_SMOOTHING)..(i + FLUX_SMOOTHING + 1).min(fluxes.len())];
+            let flux = neighbours.iter().sum::<f32>() / neighbours.len() as f32;
+            let speech =
+                energies[i] > floor + options.energy_threshold && flux > options.flux_threshold;
+            if speech {
+                1.0
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+/// Turns per-frame speech probabilities into padded sample ranges, with
+/// hysteresis so that short dips don't split a region.
+pub fn speech_regions(
+    probs: &[f32],
+    frame_len: usize,
+    n_samples: usize,
+    options: &VadOptions,
+) -> Vec<Range<usize>> {
+    let min_speech = seconds_to_samples(options.min_speech_duration);
+    let min_silence = seconds_to_samples(options.min_silence_duration);
+    let pad = seconds_to_samples(options.speech_pad);
+    let neg_threshold = (options.threshold - 0.15).max(0.01);
+
+    let mut regions: Vec<Range<usize>> = vec![];
+    let mut start = None;
+    let mut silence_start = None;
+    for (i, &prob) in probs.iter().enumerate() {
+        let position = i * frame_len;
+        if prob >= options.threshold {
+            silence_start = None;
+            start.get_or_insert(position);
+        } else if prob < neg_threshold {
+            if let Some(region_start) = start {
+                let end = *silence_start.get_or_insert(position);
+                if position - end >= min_silence {
+                    if end - region_start >= min_speech {
+                        regions.push(region_start..end);
+                    }
+                    start = None;
+                    silence_start = None;
+                }
+            }
+        }
+    }
+    if let Some(region_start) = start {
+        let end = silence_start.unwrap_or(n_samples).min(n_samples);
+        if end - region_start >= min_speech {
+            regions.push(region_start..end);
+        }
+    }
+
+    let mut padded: Vec<Range<usize>> = vec![];
+    for region in regions {
+        let region = region.start.saturating_sub(pad)..(region.end + pad).min(n_samples);
+        match padded.last_mut() {
+            Some(last) if region.start <= last.end => last.end = region.end,
+            _ => padded.push(region),
+        }
+    }
+    padded
+}
+
+/// Groups speech regions into contiguous windows of at most `max_len` samples,
+/// splitting regions that are longer than that.
+pub fn pack_regions(regions: &[Range<usize>], max_len: usize) -> Vec<Range<usize>> {
+    let mut windows: Vec<Range<usize>> = vec![];
+    for region in regions {
+        let mut start = region.start;
+        while start < region.end {
+            let piece = start..(start + max_len).min(region.end);
+            start = piece.end;
+            match windows.last_mut() {
+                Some(last) if piece.end - last.start <= max_len => last.end = piece.end,
+                _ => windows.push(piece),
+            }
+        }
+    }
+    windows
+}
+
+pub fn to_speech_region(range: &Range<usize>) -> SpeechRegion {
+    SpeechRegion {
+        start: range.start as f32 / SAMPLE_RATE as f32,
+        end: range.end as f32 / SAMPLE_RATE as f32,
+    }
+}
+
+/// Silero VAD exported to ONNX. Both the v4 graph (input, sr, h, c) and the v5
+/// graph (input, state, sr) are supported.
+pub struct SileroVad {
+    model: Model,
+}
+
+impl SileroVad {
+    pub fn new(model_path: &str) -> Result<SileroVad> {
+        let model = tract_onnx::onnx()
+            .model_for_path(model_path)?
+            .into_optimized()?
+            .into_runnable()?;
+        Ok(SileroVad { model })
+    }
+
+    pub fn speech_probs(&self, samples: &[f32]) -> Result<Vec<f32>> {
+        let is_v5 = self.model.model().inputs.len() == 3;
+        let context_len = if is_v5 { SILERO_CONTEXT } else { 0 };
+        let mut state: Vec<Tensor> = if is_v5 {
+            vec![Array3::<f32>::zeros((2, 1, 128)).into_tensor()]
+        } else {
+            vec![
+                Array3::<f32>::zeros((2, 1, 64)).into_tensor(),
+                Array3::<f32>::zeros((2, 1, 64)).into_tensor(),
+            ]
+        };
+        let mut context = Array::zeros(context_len);
+
+        let mut probs = vec![];
+        for chunk in samples.chunks(SILERO_FRAME) {
+            let mut frame = Array::zeros(SILERO_FRAME);
+            frame
+                .slice_mut(s![..chunk.len()])
+                .assign(&Array::from_vec(chunk.to_vec()));
+            let input = concatenate![Axis(0), context, frame];
+            context = input.slice(s![input.len() - context_len..]).to_owned();
+
+            let input: Tensor = input.insert_axis(Axis(0)).into();
+            let sample_rate = tensor0(SAMPLE_RATE as i64);
+            let inputs = if is_v5 {
+                tvec!(input.into(), state[0].clone().into(), sample_rate.into())
+            } else {
+                tvec!(
+                    input.into(),
+                    sample_rate.into(),
+                    state[0].clone().into(),
+                    state[1].clone().into(),
+                )
+            };
+
+            let out = self.model.run(inputs)?;
+            probs.push(*out[0].to_array_view::<f32>()?.iter().next().unwrap_or(&0.0));
+            state = out[1..]
+                .iter()
+                .map(|tensor| tensor.clone().into_tensor())
+                .collect();
+        }
+        Ok(probs)
+    }
+}
+
+#[cfg(test)]
+// Expected results are lists of ranges, often a single one.
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    /// 0.1 s frames, so the default options span whole frames.
+    const FRAME: usize = 1600;
+
+    fn probs(pattern: &str) -> Vec<f32> {
+        pattern
+            .chars()
+            .map(|c| match c {
+                'S' => 0.9,
+                '~' => 0.4,
+                _ => 0.1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn regions_use_hysteresis_and_drop_short_speech() {
+        let options = VadOptions::default();
+        // Frames between both thresholds extend the region until silence
+        // starts at frame 6; the lone speech frame at 17 is shorter than
+        // min_speech_duration.
+        let probs = probs(".SS~~~..........S.....");
+        let n_samples = probs.len() * FRAME;
+        assert_eq!(
+            speech_regions(&probs, FRAME, n_samples, &options),
+            [0..6 * FRAME + 3200]
+        );
+    }
+
+    #[test]
+    fn short_pauses_keep_regions_together() {
+        let options = VadOptions::default();
+        assert_eq!(
+            speech_regions(&probs("SSS..SSS"), FRAME, 12000, &options),
+            [0..12000]
+        );
+        // Speech ending in a pause shorter than min_silence_duration ends
+        // where the pause starts, plus padding.
+        assert_eq!(
+            speech_regions(&probs("SSS.."), FRAME, 8000, &options),
+            [0..8000]
+        );
+        assert_eq!(
+            speech_regions(&probs("SSS.."), FRAME, 20000, &options),
+            [0..4800 + 3200]
+        );
+    }
+
+    #[test]
+    fn overlapping_padding_merges_regions() {
+        let probs = probs("SSS......SSS");
+        let n_samples = probs.len() * FRAME;
+        let options = VadOptions::default();
+        assert_eq!(
+            speech_regions(&probs, FRAME, n_samples, &options),
+            [0..4800 + 3200, 14400 - 3200..n_samples]
+        );
+        let options = VadOptions {
+            speech_pad: 0.5,
+            ..VadOptions::default()
+        };
+        assert_eq!(
+            speech_regions(&probs, FRAME, n_samples, &options),
+            [0..n_samples]
+        );
+    }
+
+    #[test]
+    fn packs_regions_into_windows() {
+        assert_eq!(
+            pack_regions(&[0..4, 6..9, 12..30, 31..33], 10),
+            [0..9, 12..22, 22..30, 31..33]
+        );
+        assert_eq!(pack_regions(&[0..5, 5..10, 10..11], 10), [0..10, 10..11]);
+        assert!(pack_regions(&[], 10).is_empty());
+    }
+
+    fn tone(frequencies: &[f32], frames: usize) -> Vec<f32> {
+        (0..frames * ENERGY_FRAME)
+            .map(|i| {
+                let frequency = frequencies[i / ENERGY_FRAME % frequencies.len()];
+                0.5 * (2.0 * std::f32::consts::PI * frequency * i as f32 / SAMPLE_RATE as f32).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn energy_detector_finds_changing_tones_between_silence() {
+        let options = VadOptions::default();
+        let mut samples = vec![0.0; 20 * ENERGY_FRAME];
+        samples.extend(tone(&[300.0, 900.0, 600.0], 20));
+        samples.extend(vec![0.0; 20 * ENERGY_FRAME]);
+
+        let probs = energy_speech_probs(&samples, &options);
+        let expected: Vec<f32> = (0..60)
+            .map(|i| if (20..40).contains(&i) { 1.0 } else { 0.0 })
+            .collect();
+        assert_eq!(probs, expected);
+
+        // A loud but steady tone is hum, not speech.
+        let mut samples = vec![0.0; 20 * ENERGY_FRAME];
+        samples.extend(tone(&[300.0], 40));
+        let probs = energy_speech_probs(&samples, &options);
+        assert!(probs[25..].iter().all(|&prob| prob == 0.0));
+    }
+}